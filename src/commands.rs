@@ -0,0 +1,420 @@
+use std::fs;
+use std::path::Path;
+
+use prompt::Prompt;
+use utils;
+
+/// What an argument stands for, it decides how Tab completes it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ArgKind {
+    Number,
+    File,
+    /// A single word, quoted when it has spaces.
+    Word,
+    /// Everything after the previous arguments, as typed.
+    Rest,
+    /// One of the given words.
+    Choice(&'static [&'static str]),
+    /// Color attributes, comma separated.
+    Attributes,
+    /// The name of a command.
+    Command,
+}
+
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+/// Options that can be changed with `set`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Setting {
+    Wrap,
+    LineNumbers,
+    WrapSearch,
+}
+
+impl Setting {
+    pub fn name(self) -> &'static str {
+        match self {
+            Setting::Wrap => "wrap",
+            Setting::LineNumbers => "line-numbers",
+            Setting::WrapSearch => "wrap-search",
+        }
+    }
+}
+
+const SETTINGS: &[&str] = &["wrap", "line-numbers", "wrap-search"];
+const SWITCHES: &[&str] = &["on", "off"];
+const ATTRIBUTES: &[&str] =
+    &["red", "blue", "green", "yellow", "magenta", "cyan", "inverse", "none"];
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static [Arg],
+    pub help: &'static str,
+    /// Makes the action out of the arguments, their count is already checked.
+    action: fn(&[String]) -> Result<Prompt, String>,
+}
+
+impl Command {
+    /// The name followed by its arguments, optional ones in brackets.
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_string();
+        for arg in self.args {
+            if arg.optional {
+                usage.push_str(&format!(" [{}]", arg.name));
+            } else {
+                usage.push_str(&format!(" {}", arg.name));
+            }
+        }
+        usage
+    }
+
+    fn is_called(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    /// Argument the `index`th word after the name stands for.
+    fn arg(&self, index: usize) -> Option<&Arg> {
+        match self.args.last() {
+            Some(last) if index >= self.args.len() && last.kind == ArgKind::Rest => Some(last),
+            _ => self.args.get(index),
+        }
+    }
+}
+
+pub static COMMANDS: &[Command] = &[
+    Command {
+        name: "close",
+        aliases: &[],
+        args: &[],
+        help: "close the current grep",
+        action: |_| Ok(Prompt::CloseGrep),
+    },
+    Command {
+        name: "next",
+        aliases: &["n"],
+        args: &[],
+        help: "show the next file",
+        action: |_| Ok(Prompt::NextFile),
+    },
+    Command {
+        name: "prev",
+        aliases: &["p"],
+        args: &[],
+        help: "show the previous file",
+        action: |_| Ok(Prompt::PrevFile),
+    },
+    Command {
+        name: "goto",
+        aliases: &["g"],
+        args: &[Arg { name: "LINE", kind: ArgKind::Number, optional: false },
+                Arg { name: "FILE", kind: ArgKind::Word, optional: true }],
+        help: "go to line LINE of FILE, of merged or rotated files, or of the file on top",
+        action: goto,
+    },
+    Command {
+        name: "open",
+        aliases: &["e", "edit"],
+        args: &[Arg { name: "FILE", kind: ArgKind::File, optional: false }],
+        help: "open FILE next to the other files",
+        action: |args| Ok(Prompt::OpenFile(args[0].clone())),
+    },
+    Command {
+        name: "color",
+        aliases: &[],
+        args: &[Arg { name: "NAME", kind: ArgKind::Word, optional: false },
+                Arg { name: "REGEX", kind: ArgKind::Word, optional: false },
+                Arg { name: "ATTR[,ATTR]", kind: ArgKind::Attributes, optional: false }],
+        help: "color matches of REGEX, in place of the color called NAME",
+        action: color,
+    },
+    Command {
+        name: "set",
+        aliases: &[],
+        args: &[Arg { name: "OPTION", kind: ArgKind::Choice(SETTINGS), optional: false },
+                Arg { name: "on|off", kind: ArgKind::Choice(SWITCHES), optional: true }],
+        help: "turn wrap, line-numbers or wrap-search on, off or the other way",
+        action: set,
+    },
+    Command {
+        name: "save",
+        aliases: &["w", "write"],
+        args: &[Arg { name: "FILE", kind: ArgKind::File, optional: false }],
+        help: "write the lines of the current grep to FILE",
+        action: |args| Ok(Prompt::SaveLines(args[0].clone())),
+    },
+    Command {
+        name: "grep",
+        aliases: &[],
+        args: &[Arg { name: "PATTERN", kind: ArgKind::Rest, optional: false }],
+        help: "open a grep of PATTERN, as typed after &",
+        action: |args| Ok(Prompt::GrepPattern(args[0].clone())),
+    },
+    Command {
+        name: "help",
+        aliases: &["h", "?"],
+        args: &[Arg { name: "COMMAND", kind: ArgKind::Command, optional: true }],
+        help: "list the commands, or tell what COMMAND does",
+        action: help,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.is_called(name))
+}
+
+/// Turns a typed command line into its action.
+pub fn parse(line: &str) -> Result<Prompt, String> {
+    let words = split(line);
+    let name = match words.first() {
+        Some((_, name)) => name,
+        None => return Err("no command given".to_string()),
+    };
+    let command = find(name).ok_or_else(|| format!("unknown command: {}", name))?;
+    let mut args = Vec::new();
+    for (idx, &(begin, ref word)) in words[1..].iter().enumerate() {
+        match command.arg(idx) {
+            Some(arg) if arg.kind == ArgKind::Rest => {
+                args.push(line[begin..].trim_end().to_string());
+                break;
+            }
+            Some(_) => args.push(word.clone()),
+            None => return Err(format!("too many arguments, usage: {}", command.usage())),
+        }
+    }
+    let required = command.args.iter().filter(|arg| !arg.optional).count();
+    if args.len() < required {
+        return Err(format!("missing {}, usage: {}",
+                           command.args[args.len()].name,
+                           command.usage()));
+    }
+    (command.action)(&args)
+}
+
+/// Where the word before the end of `line` begins, and what it may be
+/// completed to.
+pub fn complete(line: &str) -> (usize, Vec<String>) {
+    let mut words = split(line);
+    let ends_word = line.ends_with(|ch: char| !ch.is_whitespace());
+    let (begin, word) = match words.pop() {
+        Some(last) if ends_word => last,
+        Some(last) => {
+            words.push(last);
+            (line.len(), String::new())
+        }
+        None => (line.len(), String::new()),
+    };
+    if words.is_empty() {
+        return (begin, command_names(&word));
+    }
+    let arg = match find(&words[0].1).and_then(|command| command.arg(words.len() - 1)) {
+        Some(arg) => arg,
+        None => return (begin, Vec::new()),
+    };
+    match arg.kind {
+        ArgKind::Choice(choices) => (begin, matching(choices, &word, " ")),
+        ArgKind::Command => (begin, command_names(&word)),
+        ArgKind::File => (begin, file_names(&word)),
+        ArgKind::Attributes => {
+            let last = word.rfind(',').map_or(0, |idx| idx + 1);
+            (begin + last, matching(ATTRIBUTES, &word[last..], ""))
+        }
+        ArgKind::Number | ArgKind::Word | ArgKind::Rest => (begin, Vec::new()),
+    }
+}
+
+fn command_names(prefix: &str) -> Vec<String> {
+    let names = COMMANDS.iter().map(|command| command.name).collect::<Vec<_>>();
+    matching(&names, prefix, " ")
+}
+
+fn matching(words: &[&str], prefix: &str, suffix: &str) -> Vec<String> {
+    words.iter()
+        .filter(|word| word.starts_with(prefix))
+        .map(|word| format!("{}{}", word, suffix))
+        .collect()
+}
+
+/// Entries of the directory `prefix` points into, directories end with `/`.
+fn file_names(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(idx) => (&prefix[..idx + 1], &prefix[idx + 1..]),
+        None => ("", prefix),
+    };
+    let path = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut names = entries.filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let hidden = file_name.starts_with('.') && !name.starts_with('.');
+            if hidden || !file_name.starts_with(name) {
+                return None;
+            }
+            let is_dir = entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false);
+            Some(format!("{}{}{}", dir, file_name, if is_dir { "/" } else { "" }))
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Words of `line` with where they begin, "..." quotes a word with spaces.
+fn split(line: &str) -> Vec<(usize, String)> {
+    let mut words = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    loop {
+        while chars.peek().is_some_and(|&(_, ch)| ch.is_whitespace()) {
+            chars.next();
+        }
+        let begin = match chars.peek() {
+            Some(&(idx, _)) => idx,
+            None => break,
+        };
+        let mut word = String::new();
+        let mut quoted = false;
+        while let Some(&(_, ch)) = chars.peek() {
+            if ch.is_whitespace() && !quoted {
+                break;
+            }
+            chars.next();
+            match ch {
+                '"' => quoted = !quoted,
+                '\\' if quoted => word.extend(chars.next().map(|(_, ch)| ch)),
+                _ => word.push(ch),
+            }
+        }
+        words.push((begin, word));
+    }
+    words
+}
+
+fn goto(args: &[String]) -> Result<Prompt, String> {
+    match args[0].parse::<usize>() {
+        Ok(line) if line > 0 => Ok(Prompt::Goto(line, args.get(1).cloned())),
+        _ => Err(format!("invalid line number: {}", args[0])),
+    }
+}
+
+fn color(args: &[String]) -> Result<Prompt, String> {
+    let mut attributes = Vec::new();
+    for attr in args[2].split(',') {
+        match utils::Attribute::from_name(attr.trim()) {
+            Some(attr) => attributes.push(attr),
+            None => return Err(format!("unknown color attribute: {}", attr)),
+        }
+    }
+    Ok(Prompt::Color(args[0].clone(), utils::DecorationPattern::new(&args[1], attributes)))
+}
+
+fn set(args: &[String]) -> Result<Prompt, String> {
+    let setting = match args[0].as_str() {
+        "wrap" => Setting::Wrap,
+        "line-numbers" => Setting::LineNumbers,
+        "wrap-search" => Setting::WrapSearch,
+        other => return Err(format!("unknown option: {}", other)),
+    };
+    let value = match args.get(1).map(|value| value.as_str()) {
+        None => None,
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        Some(other) => return Err(format!("expected on or off, not {}", other)),
+    };
+    Ok(Prompt::Set(setting, value))
+}
+
+fn help(args: &[String]) -> Result<Prompt, String> {
+    match args.first() {
+        None => {
+            let names = COMMANDS.iter().map(|command| command.name).collect::<Vec<_>>();
+            Ok(Prompt::Help(format!("Commands: {}, help COMMAND tells more",
+                                    names.join(", "))))
+        }
+        Some(name) => {
+            let command = find(name).ok_or_else(|| format!("unknown command: {}", name))?;
+            Ok(Prompt::Help(format!("{}: {}", command.usage(), command.help)))
+        }
+    }
+}
+
+#[test]
+fn split_words() {
+    assert_eq!(split(" goto  12 "), vec![(1, "goto".to_string()), (7, "12".to_string())]);
+    assert_eq!(split(r#"color "a b" "c\"d" """#),
+               vec![(0, "color".to_string()),
+                    (6, "a b".to_string()),
+                    (12, "c\"d".to_string()),
+                    (19, String::new())]);
+}
+
+#[test]
+fn parse_commands() {
+    assert!(matches!(parse("goto 1200"), Ok(Prompt::Goto(1200, None))));
+    assert!(matches!(parse("goto 12 app.log.1"),
+                     Ok(Prompt::Goto(12, Some(ref file))) if file == "app.log.1"));
+    assert!(parse("g 0").is_err());
+    assert!(matches!(parse("close"), Ok(Prompt::CloseGrep)));
+    assert!(matches!(parse("edit out.log"), Ok(Prompt::OpenFile(ref file)) if file == "out.log"));
+    assert!(matches!(parse("set wrap off"), Ok(Prompt::Set(Setting::Wrap, Some(false)))));
+    assert!(matches!(parse("set line-numbers"), Ok(Prompt::Set(Setting::LineNumbers, None))));
+    assert!(matches!(parse("grep -C 2  a or b "),
+                     Ok(Prompt::GrepPattern(ref pattern)) if pattern == "-C 2  a or b"));
+    match parse("color ids \"id [0-9]+\" red,inverse") {
+        Ok(Prompt::Color(name, decoration)) => {
+            assert_eq!(name, "ids");
+            assert_eq!(decoration,
+                       utils::DecorationPattern::new("id [0-9]+",
+                                                     vec![utils::Attribute::Red,
+                                                          utils::Attribute::Inverse]));
+        }
+        _ => panic!("color not parsed"),
+    }
+    assert!(matches!(parse("help set"), Ok(Prompt::Help(ref text))
+                     if text.starts_with("set OPTION [on|off]: ")));
+
+    assert_eq!(parse("bogus").err(), Some("unknown command: bogus".to_string()));
+    assert_eq!(parse("save").err(), Some("missing FILE, usage: save FILE".to_string()));
+    assert_eq!(parse("close now").err(),
+               Some("too many arguments, usage: close".to_string()));
+    assert!(parse("color a b purple").is_err());
+    assert!(parse("set wrap maybe").is_err());
+    assert!(parse("").is_err());
+}
+
+#[test]
+fn complete_commands() {
+    assert_eq!(complete(""), (0, command_names("")));
+    assert_eq!(complete("g"), (0, vec!["goto ".to_string(), "grep ".to_string()]));
+    assert_eq!(complete("set "), (4, vec!["wrap ".to_string(),
+                                         "line-numbers ".to_string(),
+                                         "wrap-search ".to_string()]));
+    assert_eq!(complete("set wrap o"), (9, vec!["on ".to_string(), "off ".to_string()]));
+    assert_eq!(complete("help cl"), (5, vec!["close ".to_string()]));
+    assert_eq!(complete("color a b red,in"), (14, vec!["inverse".to_string()]));
+    assert_eq!(complete("goto 1"), (5, Vec::new()));
+    assert_eq!(complete("bogus a"), (6, Vec::new()));
+}
+
+#[test]
+fn complete_file_names() {
+    let dir = utils::TempDir::new("complete");
+    fs::create_dir(dir.join("app.logs")).unwrap();
+    for name in &["app.log", "app.log.1", "other.log", ".app.log.swp"] {
+        fs::File::create(dir.join(name)).unwrap();
+    }
+    let dir_name = format!("{}/", dir.path.display());
+    let (begin, names) = complete(&format!("open {}app", dir_name));
+    let hidden = file_names(&format!("{}.", dir_name));
+
+    assert_eq!(begin, 5);
+    assert_eq!(names,
+               vec![dir_name.clone() + "app.log", dir_name.clone() + "app.log.1",
+                    dir_name.clone() + "app.logs/"]);
+    assert_eq!(hidden, vec![dir_name + ".app.log.swp"]);
+}
@@ -2,6 +2,10 @@ use utils;
 use pager::*;
 use greps::*;
 use ncurses::*;
+use libc;
+use std::ffi::CString;
+use std::time::{Duration, Instant};
+use unicode_width::UnicodeWidthStr;
 
 
 static COLOR_BACKGROUND: i16 = COLOR_BLACK;
@@ -10,53 +14,258 @@ static COLOR_FOREGROUND: i16 = COLOR_WHITE;
 static COLOR_PAIR_DEFAULT: i16 = 1;
 static COLOR_PAIR_RED: i16 = 2;
 static COLOR_PAIR_BLUE: i16 = 3;
+static COLOR_PAIR_GREEN: i16 = 4;
+static COLOR_PAIR_YELLOW: i16 = 5;
+static COLOR_PAIR_MAGENTA: i16 = 6;
+static COLOR_PAIR_CYAN: i16 = 7;
+
+/// Colors of the tags telling apart lines of merged files.
+static SOURCE_TAG_COLORS: [utils::Attribute; 6] = [utils::Attribute::Green,
+                                                   utils::Attribute::Yellow,
+                                                   utils::Attribute::Magenta,
+                                                   utils::Attribute::Cyan,
+                                                   utils::Attribute::Blue,
+                                                   utils::Attribute::Red];
+
+/// How long `input_key` waits before reporting `Key::Null`, so the caller
+/// can pick up lines that are still being loaded.
+static INPUT_TIMEOUT_MS: i32 = 100;
+
+/// Terminals wrap pasted text in these, once bracketed paste is on.
+static PASTE_ON: &str = "\x1b[?2004h";
+static PASTE_OFF: &str = "\x1b[?2004l";
+static PASTE_BEGIN: &str = "[200~";
+static PASTE_END: &str = "[201~";
+
+/// How long info and warning messages stay, errors wait for a key.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub struct CursesPager {
     userbar_height: i32,
+    /// Long lines wrap onto following rows, otherwise they are cut at the
+    /// right edge and scrolled sideways.
+    pub wrap: bool,
+    /// Display column the visible part of cut lines starts at.
+    pub column_offset: usize,
+    /// Lines are prefixed with their number in the file they come from.
+    pub line_numbers: bool,
+    /// Shown under the status until the next key, or until it times out.
+    message: Option<(Level, String, Instant)>,
+    /// Keys are pasted text, not commands.
+    pasting: bool,
+    /// Terminal keys are read from and the pager is drawn on.
+    tty: *mut libc::FILE,
 }
 
 impl CursesPager {
     pub fn new() -> CursesPager {
-        CursesPager { userbar_height: 2 }
+        CursesPager {
+            userbar_height: 3,
+            wrap: true,
+            column_offset: 0,
+            line_numbers: false,
+            message: None,
+            pasting: false,
+            tty: ::std::ptr::null_mut(),
+        }
+    }
+
+    /// Posts feedback for the user, replacing the previous message.
+    pub fn notify(&mut self, level: Level, text: String) {
+        self.message = Some((level, text, Instant::now()));
+    }
+
+    pub fn info(&mut self, text: String) {
+        self.notify(Level::Info, text);
+    }
+
+    pub fn warn(&mut self, text: String) {
+        self.notify(Level::Warning, text);
+    }
+
+    pub fn error(&mut self, text: String) {
+        self.notify(Level::Error, text);
+    }
+
+    pub fn clear_message(&mut self) {
+        self.message = None;
+    }
+
+    /// Drops an info or a warning shown for long enough, returns whether it
+    /// did.
+    pub fn expire_message(&mut self) -> bool {
+        let expired = match self.message {
+            Some((Level::Error, _, _)) | None => false,
+            Some((_, _, posted)) => posted.elapsed() >= MESSAGE_TIMEOUT,
+        };
+        if expired {
+            self.message = None;
+        }
+        expired
+    }
+
+    /// Cut lines are scrolled by half of the screen.
+    pub fn scroll_right(&mut self) {
+        self.column_offset += (self.term_size().0 / 2).max(1);
+    }
+
+    pub fn scroll_left(&mut self) {
+        self.column_offset = self.column_offset.saturating_sub((self.term_size().0 / 2).max(1));
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.column_offset = 0;
     }
 
     pub fn initialize(&mut self) {
-        initscr();
+        // Multibyte characters are only printed as such in the user's locale.
+        let locale = CString::new("").unwrap();
+        unsafe {
+            libc::setlocale(libc::LC_CTYPE, locale.as_ptr());
+        }
+        // Keys are read from the controlling terminal, stdin may be a pipe
+        // feeding the log.
+        let tty = unsafe {
+            let path = CString::new("/dev/tty").unwrap();
+            let mode = CString::new("r+").unwrap();
+            libc::fopen(path.as_ptr(), mode.as_ptr())
+        };
+        if tty.is_null() {
+            initscr();
+        } else {
+            let screen = newterm(None, tty, tty);
+            set_term(screen);
+            self.tty = tty;
+            self.write_tty(PASTE_ON);
+        }
+        timeout(INPUT_TIMEOUT_MS);
         keypad(stdscr(), true);
         noecho();
         start_color();
 
         init_pair(COLOR_PAIR_RED, COLOR_RED, COLOR_BACKGROUND);
         init_pair(COLOR_PAIR_BLUE, COLOR_BLUE, COLOR_BACKGROUND);
+        init_pair(COLOR_PAIR_GREEN, COLOR_GREEN, COLOR_BACKGROUND);
+        init_pair(COLOR_PAIR_YELLOW, COLOR_YELLOW, COLOR_BACKGROUND);
+        init_pair(COLOR_PAIR_MAGENTA, COLOR_MAGENTA, COLOR_BACKGROUND);
+        init_pair(COLOR_PAIR_CYAN, COLOR_CYAN, COLOR_BACKGROUND);
         init_pair(COLOR_PAIR_DEFAULT, COLOR_FOREGROUND, COLOR_BACKGROUND);
         bkgd(' ' as chtype | COLOR_PAIR(COLOR_PAIR_DEFAULT) as chtype);
     }
 
-    pub fn print_logs(&mut self,
-                      lines: &[utils::Line],
-                      greps: Vec<utils::DecorationPattern>)
-                      -> usize {
+    fn write_tty(&mut self, sequence: &str) {
+        let sequence = CString::new(sequence).unwrap();
+        unsafe {
+            libc::fputs(sequence.as_ptr(), self.tty);
+            libc::fflush(self.tty);
+        }
+    }
+
+    /// Tells the Esc key from the start and the end of pasted text, keys
+    /// read past Esc are put back otherwise.
+    fn escape(&mut self) -> Key {
+        timeout(0);
+        let mut sequence = String::new();
+        while let Some(WchResult::Char(ch)) = get_wch() {
+            match ::std::char::from_u32(ch) {
+                Some(ch) => sequence.push(ch),
+                None => break,
+            }
+            if !PASTE_BEGIN.starts_with(&sequence) && !PASTE_END.starts_with(&sequence) ||
+               sequence.len() == PASTE_BEGIN.len() {
+                break;
+            }
+        }
+        timeout(INPUT_TIMEOUT_MS);
+        if sequence == PASTE_BEGIN || sequence == PASTE_END {
+            self.pasting = sequence == PASTE_BEGIN;
+            return self.input_key();
+        }
+        for ch in sequence.chars().rev() {
+            unget_wch(ch as u32);
+        }
+        Key::Esc
+    }
+
+    /// Number of terminal rows left for log lines.
+    pub fn logs_height(&mut self) -> usize {
+        (self.term_size().1 as i32 - self.userbar_height).max(0) as usize
+    }
+
+    /// Prints lines of the current grep from `index` on. `sources` names the
+    /// merged files lines come from, each line is then prefixed with a
+    /// colored tag of its file, after its number when `line_numbers` are on.
+    /// Context lines of a grep are dimmed, with a separator where lines were
+    /// left out between them.
+    pub fn print_logs(&mut self, logs: &Greps, index: usize, sources: &[String]) -> usize {
+        let tag_width = sources.iter().map(|s| s.width()).max().unwrap_or(0);
         let mut printed_lines = 0;
         let end_height = (self.term_size().1 as i32) - self.userbar_height;
+        let lines_count = logs.lines_count();
+        let number_width = (index..lines_count)
+            .take(end_height.max(0) as usize)
+            .map(|idx| logs.position(idx).number.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let greps = logs.decorations();
 
-        for line in lines {
-            if self.cursor_pos().1 as i32 >= end_height {
+        for idx in index..lines_count {
+            let line = logs.line(idx);
+            let position = logs.position(idx);
+            let mut row = self.cursor_pos().1;
+            if line.after_gap && row > 0 && (row as i32) < end_height {
+                attron(A_DIM());
+                self.print("--");
+                attroff(A_DIM());
+                self.clear_line_from((2, row));
+                row += 1;
+                self.mv_cursor((0, row));
+            }
+            if row as i32 >= end_height {
                 return printed_lines;
             } else {
-                for word in &line.decorate(greps.clone()) {
-                    self.print_decoration(word);
+                if self.line_numbers {
+                    let gutter = format!("{:>width$} ", position.number, width = number_width);
+                    self.print_decoration(&utils::Decorations::Some(vec![utils::Attribute::Yellow],
+                                                                    &gutter));
+                }
+                if let Some(source) = sources.get(position.source) {
+                    let padding = tag_width - source.width();
+                    let tag = format!("{}{:padding$}| ", source, "", padding = padding);
+                    let color = SOURCE_TAG_COLORS[position.source % SOURCE_TAG_COLORS.len()];
+                    self.print_decoration(&utils::Decorations::Some(vec![color], &tag));
+                }
+                let words = line.decorate(greps.clone());
+                if line.context {
+                    attron(A_DIM());
+                }
+                if self.wrap {
+                    for word in &words {
+                        self.print_decoration(word);
+                    }
+                } else {
+                    self.print_cut(&words);
+                }
+                if line.context {
+                    attroff(A_DIM());
                 }
             }
-            let current_pos = self.cursor_pos();
-            self.clear_line_from(current_pos);
-            if self.cursor_pos().1 as i32 <= end_height - 1 {
-                // let (_, curr_y) = self.cursor_pos();
-                // self.mv_cursor((0, curr_y + 1));
+            // A line filling its last row up already moved the cursor on.
+            let (x, y) = self.cursor_pos();
+            let next_row = if x == 0 && y > row {
+                y
+            } else {
+                self.clear_line_from((x, y));
+                y + 1
+            };
+            self.mv_cursor((0, next_row));
+            if (next_row as i32) < end_height {
                 printed_lines += 1;
             }
         }
 
-        while self.cursor_pos().1 as i32 != (self.term_size().1 as i32 - self.userbar_height) {
+        while (self.cursor_pos().1 as i32) < end_height {
             let (_, curr_y) = self.cursor_pos();
             self.print("~");
             let current_pos = self.cursor_pos();
@@ -64,55 +273,63 @@ impl CursesPager {
             self.mv_cursor((0, curr_y + 1));
         }
 
-        return printed_lines;
+        printed_lines
+    }
+
+    /// Prints the part of a line within the screen width from
+    /// `column_offset` on, decorations keep to their own text.
+    fn print_cut(&mut self, words: &[utils::Decorations]) {
+        let from = self.column_offset;
+        let to = from + self.term_size().0.saturating_sub(self.cursor_pos().0);
+        let mut column = 0;
+        for word in words {
+            let (slice, end) = match *word {
+                utils::Decorations::None(buffer) |
+                utils::Decorations::Some(_, buffer) => column_slice(buffer, column, from, to),
+            };
+            column = end;
+            match *word {
+                utils::Decorations::None(_) => {
+                    self.print_decoration(&utils::Decorations::None(&slice))
+                }
+                utils::Decorations::Some(ref attrs, _) => {
+                    self.print_decoration(&utils::Decorations::Some(attrs.clone(), &slice))
+                }
+            }
+            if column >= to {
+                break;
+            }
+        }
     }
 
     fn print_decoration(&mut self, decoration: &utils::Decorations) {
-        match decoration {
-            &utils::Decorations::None(ref buffer) => {
-                let offset = self.userbar_height as usize;
+        let offset = self.userbar_height as usize;
+        match *decoration {
+            utils::Decorations::None(buffer) => {
                 self.print_buffer(buffer, offset);
             }
-            &utils::Decorations::Some(ref attrs, ref buffer) => {
+            utils::Decorations::Some(ref attrs, buffer) => {
                 for attr in attrs {
-                    match attr {
-                        &utils::Attribute::Inverse => {
-                            attron(A_REVERSE());
-                        }
-                        &utils::Attribute::Red => {
-                            attron(COLOR_PAIR(COLOR_PAIR_RED));
-                        }
-                        &utils::Attribute::Blue => {
-                            attron(COLOR_PAIR(COLOR_PAIR_BLUE));
-                        }
-                        _ => {}    
-                    }
+                    attron(attribute_flags(*attr));
                 }
-                let offset = self.userbar_height as usize;
                 self.print_buffer(buffer, offset);
                 for attr in attrs {
-                    match attr {
-                        &utils::Attribute::Inverse => {
-                            attroff(A_REVERSE());
-                        }
-                        &utils::Attribute::Red => {
-                            attroff(COLOR_PAIR(COLOR_PAIR_RED));
-                        }
-                        &utils::Attribute::Blue => {
-                            attroff(COLOR_PAIR(COLOR_PAIR_BLUE));
-                        }
-                        _ => {}    
-                    }
+                    attroff(attribute_flags(*attr));
                 }
             }
         }
     }
 
-    pub fn status(&mut self, greps: &Greps) {
+    pub fn status(&mut self, file_label: &str, greps: &Greps) {
         self.clear_line();
-        let mut idx = 0;
+        if !file_label.is_empty() {
+            attron(A_BOLD());
+            self.print(file_label);
+            attroff(A_BOLD());
+            self.print(" ");
+        }
         let selected = greps.selected;
-        for grep in &greps.greps {
+        for (idx, grep) in greps.greps.iter().enumerate() {
             if idx == selected {
                 attron(A_REVERSE());
                 self.print(&grep.patern);
@@ -121,18 +338,67 @@ impl CursesPager {
                 self.print(&grep.patern);
             }
             self.print(" ");
-            idx += 1;
         }
         let pos = self.cursor_pos();
         self.clear_line_from(pos);
+        // The search match and where the top line sits in its file, at the
+        // right end.
+        let mut position = Vec::new();
+        if let Some((current, count)) = greps.search_position() {
+            position.push(format!("match {}/{}", current, count));
+        }
+        let index = greps.current_grep().line_index;
+        if self.line_numbers && index < greps.lines_count() {
+            let top = greps.position(index);
+            position.push(format!("line {}, byte {}", top.number, top.offset));
+        }
+        let position = position.join("  ");
+        let column = self.term_size().0.saturating_sub(position.len() + 1);
+        if !position.is_empty() && column > pos.0 {
+            self.mv_cursor((column, pos.1));
+            self.print(&position);
+        }
+        self.print_message(pos.1 + 1);
+        self.mv_cursor((0, pos.1 + 2));
+    }
+
+    fn print_message(&mut self, row: usize) {
+        self.clear_line_from((0, row));
+        if let Some((level, text, _)) = self.message.clone() {
+            let width = self.term_size().0.saturating_sub(1);
+            let attrs = match level {
+                Level::Info => A_NORMAL(),
+                Level::Warning => COLOR_PAIR(COLOR_PAIR_YELLOW),
+                Level::Error => COLOR_PAIR(COLOR_PAIR_RED) | A_BOLD(),
+            };
+            attron(attrs);
+            self.print(&fit_to_cells(&text, 0, width.max(1), width));
+            attroff(attrs);
+        }
+    }
+}
+
+fn attribute_flags(attr: utils::Attribute) -> attr_t {
+    match attr {
+        utils::Attribute::None => A_NORMAL(),
+        utils::Attribute::Inverse => A_REVERSE(),
+        utils::Attribute::Red => COLOR_PAIR(COLOR_PAIR_RED),
+        utils::Attribute::Blue => COLOR_PAIR(COLOR_PAIR_BLUE),
+        utils::Attribute::Green => COLOR_PAIR(COLOR_PAIR_GREEN),
+        utils::Attribute::Yellow => COLOR_PAIR(COLOR_PAIR_YELLOW),
+        utils::Attribute::Magenta => COLOR_PAIR(COLOR_PAIR_MAGENTA),
+        utils::Attribute::Cyan => COLOR_PAIR(COLOR_PAIR_CYAN),
     }
 }
 
 impl Drop for CursesPager {
     fn drop(&mut self) {
-        let last_line = (self.term_size().1 - 1) as usize;
+        let last_line = self.term_size().1 - 1;
         self.mv_cursor((last_line, 0));
         endwin();
+        if !self.tty.is_null() {
+            self.write_tty(PASTE_OFF);
+        }
     }
 }
 
@@ -145,7 +411,13 @@ impl TermOperations for CursesPager {
     }
 
     fn print(&mut self, slice: &str) {
-        printw(slice);
+        addstr(slice);
+    }
+
+    fn print_highlighted(&mut self, text: &str) {
+        attron(A_REVERSE());
+        addstr(text);
+        attroff(A_REVERSE());
     }
 
     fn clear(&mut self) {
@@ -164,39 +436,56 @@ impl TermOperations for CursesPager {
     }
 
     fn clear_line(&mut self) {
-        let (max_x, _) = self.term_size();
         let (_, y) = self.cursor_pos();
-
-        self.mv_cursor((0, y as usize));
-        let mut clear_line = String::new();
-        for _ in 0..max_x {
-            clear_line.push(' ');
-        }
-        self.print(&clear_line);
-        self.mv_cursor((0, y as usize));
+        self.mv_cursor((0, y));
+        clrtoeol();
     }
 
+    /// Clears the rest of the row from `pos`, the cursor is left there.
     fn clear_line_from(&mut self, pos: (usize, usize)) {
-        let (max_x, _) = self.term_size();
-        let (x, _) = pos;
-        let mut clear_line = String::new();
-        for _ in (x - 1)..max_x - 1 {
-            clear_line.push(' ');
-        }
-        self.print(&clear_line);
+        self.mv_cursor(pos);
+        clrtoeol();
     }
 
     fn input_key(&mut self) -> Key {
-        match getch() as u8 {
-            27 => Key::Esc,
-            2 => Key::Down,
-            3 => Key::Up,
-            4 => Key::Left,
-            5 => Key::Right,
-            9 => Key::Tab,
-            10 => Key::Enter,
-            23 => Key::Ctrl('w'),
-            ch => Key::Char(ch as char),
+        let ch = match get_wch() {
+            Some(WchResult::Char(ch)) => ch,
+            Some(WchResult::KeyCode(code)) => {
+                return match code {
+                    KEY_DOWN => Key::Down,
+                    KEY_UP => Key::Up,
+                    KEY_LEFT => Key::Left,
+                    KEY_RIGHT => Key::Right,
+                    KEY_HOME => Key::Home,
+                    KEY_END => Key::End,
+                    KEY_NPAGE => Key::PageDown,
+                    KEY_PPAGE => Key::PageUp,
+                    KEY_BACKSPACE => Key::Backspace,
+                    KEY_DC => Key::Delete,
+                    KEY_IC => Key::Insert,
+                    KEY_ENTER => Key::Enter,
+                    _ if code > KEY_F0 && code <= KEY_F0 + 12 => Key::F((code - KEY_F0) as u8),
+                    _ => Key::Null,
+                };
+            }
+            None => return Key::Null,
+        };
+        let ch = match ::std::char::from_u32(ch) {
+            Some(ch) => ch,
+            None => return Key::Null,
+        };
+        // Line breaks and tabs of pasted text are typed as spaces.
+        if self.pasting && ch != '\x1b' {
+            return Key::Char(if ch.is_control() { ' ' } else { ch });
+        }
+        match ch {
+            '\x1b' => self.escape(),
+            '\t' => Key::Tab,
+            '\n' | '\r' => Key::Enter,
+            '\x08' | '\x7f' => Key::Backspace,
+            '\x01'..='\x1a' => Key::Ctrl((ch as u8 - 1 + b'a') as char),
+            _ if ch.is_control() => Key::Null,
+            _ => Key::Char(ch),
         }
     }
 }
\ No newline at end of file
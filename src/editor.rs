@@ -0,0 +1,233 @@
+use pager::*;
+use unicode_width::UnicodeWidthStr;
+
+/// What a key did to the line being edited.
+#[derive(Debug, PartialEq)]
+pub enum Edit {
+    Changed,
+    Moved,
+    /// Not an editing key, the caller handles it.
+    Ignored,
+}
+
+/// Text typed at a prompt, edited with the usual shell keys.
+pub struct LineEditor {
+    text: String,
+    /// Byte index of the cursor in `text`.
+    cursor: usize,
+}
+
+impl LineEditor {
+    /// Starts with `text` and the cursor at its end.
+    pub fn new(text: &str) -> LineEditor {
+        LineEditor {
+            text: text.to_string(),
+            cursor: text.len(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Text up to the cursor, what completion looks at.
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    /// Puts `text` in place of what lies between `begin` and the cursor.
+    pub fn replace_before_cursor(&mut self, begin: usize, text: &str) {
+        self.text.replace_range(begin..self.cursor, text);
+        self.cursor = begin + text.len();
+    }
+
+    pub fn handle(&mut self, key: Key) -> Edit {
+        match key {
+            Key::Char(ch) => {
+                self.text.insert(self.cursor, ch);
+                self.cursor += ch.len_utf8();
+                Edit::Changed
+            }
+            Key::Backspace | Key::Ctrl('h') => {
+                let begin = self.prev_boundary();
+                self.remove(begin, self.cursor)
+            }
+            Key::Delete | Key::Ctrl('d') => {
+                let end = self.next_boundary();
+                self.remove(self.cursor, end)
+            }
+            Key::Left | Key::Ctrl('b') => self.move_to(self.prev_boundary()),
+            Key::Right | Key::Ctrl('f') => self.move_to(self.next_boundary()),
+            Key::Home | Key::Ctrl('a') => self.move_to(0),
+            Key::End | Key::Ctrl('e') => self.move_to(self.text.len()),
+            Key::Ctrl('u') => self.remove(0, self.cursor),
+            Key::Ctrl('k') => self.remove(self.cursor, self.text.len()),
+            Key::Ctrl('w') => {
+                // Spaces before the cursor go along with the word.
+                let before = self.text[..self.cursor].trim_end();
+                let begin = before.rfind(char::is_whitespace).map_or(0, |idx| idx + 1);
+                self.remove(begin, self.cursor)
+            }
+            _ => Edit::Ignored,
+        }
+    }
+
+    /// Prints `prefix` and the text over the current row, scrolled so the
+    /// cursor stays on screen, and puts the terminal cursor there.
+    pub fn render<P>(&self, pager: &mut P, prefix: &str)
+        where P: TermOperations
+    {
+        let (width, _) = pager.term_size();
+        let (_, row) = pager.cursor_pos();
+        let room = width.saturating_sub(prefix.width() + 1);
+        let mut skip = 0;
+        while self.text[skip..self.cursor].width() > room {
+            skip += self.text[skip..].chars().next().map_or(1, |ch| ch.len_utf8());
+        }
+        pager.clear_line();
+        pager.print(prefix);
+        let shown = fit_to_cells(&self.text[skip..], 0, width.max(1), room);
+        pager.print(&shown);
+        let column = prefix.width() + self.text[skip..self.cursor].width();
+        pager.mv_cursor((column, row));
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.cursor].char_indices().next_back().map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_boundary(&self) -> usize {
+        let next = self.text[self.cursor..].chars().next();
+        next.map_or(self.cursor, |ch| self.cursor + ch.len_utf8())
+    }
+
+    fn move_to(&mut self, cursor: usize) -> Edit {
+        self.cursor = cursor;
+        Edit::Moved
+    }
+
+    fn remove(&mut self, begin: usize, end: usize) -> Edit {
+        if begin == end {
+            return Edit::Moved;
+        }
+        self.text.replace_range(begin..end, "");
+        self.cursor = begin;
+        Edit::Changed
+    }
+}
+
+/// Candidates for the word before the cursor. The first Tab fills in what
+/// they have in common, the next ones go through them in turn.
+pub struct Completion {
+    /// Byte index where the completed word starts.
+    begin: usize,
+    candidates: Vec<String>,
+    selected: Option<usize>,
+}
+
+impl Completion {
+    pub fn new(begin: usize, candidates: Vec<String>) -> Completion {
+        Completion {
+            begin,
+            candidates,
+            selected: None,
+        }
+    }
+
+    pub fn begin(&self) -> usize {
+        self.begin
+    }
+
+    pub fn candidates(&self) -> &[String] {
+        &self.candidates
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Completes the word in `editor` one step further, returns whether
+    /// another Tab would still change it.
+    pub fn next(&mut self, editor: &mut LineEditor) -> bool {
+        if self.candidates.is_empty() {
+            return false;
+        }
+        let typed = editor.before_cursor().len().saturating_sub(self.begin);
+        let common = common_prefix(&self.candidates);
+        if self.selected.is_none() && (common.len() > typed || self.candidates.len() == 1) {
+            editor.replace_before_cursor(self.begin, common);
+            return self.candidates.len() > 1;
+        }
+        let selected = self.selected.map_or(0, |idx| (idx + 1) % self.candidates.len());
+        self.selected = Some(selected);
+        editor.replace_before_cursor(self.begin, &self.candidates[selected]);
+        true
+    }
+}
+
+fn common_prefix(candidates: &[String]) -> &str {
+    let first = &candidates[0];
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first.char_indices()
+            .zip(candidate.chars())
+            .take_while(|&((idx, a), b)| a == b && idx < len)
+            .last()
+            .map_or(0, |((idx, a), _)| idx + a.len_utf8());
+    }
+    &first[..len]
+}
+
+#[test]
+fn edit_line() {
+    let mut editor = LineEditor::new("timeout");
+    editor.handle(Key::Backspace);
+    editor.handle(Key::Home);
+    editor.handle(Key::Right);
+    assert_eq!(editor.handle(Key::Char('é')), Edit::Changed);
+    assert_eq!(editor.text(), "téimeou");
+    editor.handle(Key::Left);
+    editor.handle(Key::Delete);
+    assert_eq!(editor.text(), "timeou");
+    assert_eq!(editor.handle(Key::Tab), Edit::Ignored);
+
+    editor.handle(Key::Ctrl('e'));
+    for ch in " or  refused ".chars() {
+        editor.handle(Key::Char(ch));
+    }
+    editor.handle(Key::Ctrl('w'));
+    assert_eq!(editor.text(), "timeou or  ");
+    editor.handle(Key::Ctrl('a'));
+    editor.handle(Key::Ctrl('f'));
+    editor.handle(Key::Ctrl('k'));
+    assert_eq!(editor.text(), "t");
+    assert_eq!(editor.handle(Key::Delete), Edit::Moved);
+    editor.handle(Key::Ctrl('u'));
+    assert_eq!(editor.text(), "");
+    assert_eq!(editor.handle(Key::Backspace), Edit::Moved);
+}
+
+#[test]
+fn complete_words() {
+    let candidates = vec!["goto ".to_string(), "grep ".to_string(), "gray".to_string()];
+    let mut editor = LineEditor::new("x g");
+    let mut completion = Completion::new(2, candidates.clone());
+    assert!(completion.next(&mut editor));
+    assert_eq!(completion.selected(), Some(0));
+    assert_eq!(editor.text(), "x goto ");
+    completion.next(&mut editor);
+    completion.next(&mut editor);
+    completion.next(&mut editor);
+    assert_eq!(editor.text(), "x goto ");
+
+    let mut editor = LineEditor::new("gr");
+    let mut completion = Completion::new(0, vec!["grep ".to_string(), "greps".to_string()]);
+    assert!(completion.next(&mut editor));
+    assert_eq!(editor.text(), "grep");
+    assert_eq!(completion.selected(), None);
+
+    let mut editor = LineEditor::new("he");
+    assert!(!Completion::new(0, vec!["help ".to_string()]).next(&mut editor));
+    assert_eq!(editor.text(), "help ");
+    assert!(!Completion::new(0, Vec::new()).next(&mut editor));
+}
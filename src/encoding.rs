@@ -0,0 +1,244 @@
+use std::io::prelude::*;
+use std::io;
+use std::borrow::Cow;
+use std::fmt::Write as FmtWrite;
+
+/// Number of bytes at the beginning of the input checked by `looks_binary`.
+pub const BINARY_CHECK_LEN: usize = 8192;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_lowercase().as_ref() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    /// UTF-16 text carries a byte order mark, which overrides the default.
+    pub fn detect(self, header: &[u8]) -> Encoding {
+        if self != Encoding::Utf8 {
+            self
+        } else if header.starts_with(&[0xff, 0xfe]) {
+            Encoding::Utf16Le
+        } else if header.starts_with(&[0xfe, 0xff]) {
+            Encoding::Utf16Be
+        } else {
+            self
+        }
+    }
+
+    /// Whether lines can be told apart, and decoded, one by one on their
+    /// bytes. UTF-16 has to go through `Utf16Reader` first.
+    pub fn is_byte_oriented(self) -> bool {
+        self == Encoding::Utf8 || self == Encoding::Latin1
+    }
+
+    /// Decodes a line of a byte oriented encoding, invalid bytes and control
+    /// characters other than tab, which the terminal can't print, are shown
+    /// as `\xNN` escapes.
+    pub fn decode_line(self, bytes: &[u8]) -> Cow<'_, str> {
+        match self {
+            Encoding::Latin1 => decode_latin1(bytes),
+            _ => decode_utf8(bytes),
+        }
+    }
+}
+
+fn is_control(byte: u8) -> bool {
+    (byte < 0x20 && byte != b'\t') || byte == 0x7f
+}
+
+fn escape(text: &mut String, byte: u8) {
+    let _ = write!(text, "\\x{:02X}", byte);
+}
+
+fn decode_utf8(bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = ::std::str::from_utf8(bytes) {
+        if !text.bytes().any(is_control) {
+            return Cow::Borrowed(text);
+        }
+    }
+    let mut text = String::with_capacity(bytes.len() + 8);
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (valid, invalid) = match ::std::str::from_utf8(rest) {
+            Ok(valid) => (valid, 0),
+            Err(err) => {
+                let valid = ::std::str::from_utf8(&rest[..err.valid_up_to()]).unwrap();
+                (valid, err.error_len().unwrap_or(rest.len() - err.valid_up_to()))
+            }
+        };
+        for ch in valid.chars() {
+            if ch.is_ascii() && is_control(ch as u8) {
+                escape(&mut text, ch as u8);
+            } else {
+                text.push(ch);
+            }
+        }
+        for &byte in &rest[valid.len()..valid.len() + invalid] {
+            escape(&mut text, byte);
+        }
+        rest = &rest[valid.len() + invalid..];
+    }
+    Cow::Owned(text)
+}
+
+/// C1 control codes are escaped as well.
+fn decode_latin1(bytes: &[u8]) -> Cow<'_, str> {
+    if bytes.iter().all(|&b| b < 0x80 && !is_control(b)) {
+        return Cow::Borrowed(::std::str::from_utf8(bytes).unwrap());
+    }
+    let mut text = String::with_capacity(bytes.len() + 8);
+    for &byte in bytes {
+        match byte {
+            0x80..=0x9f => escape(&mut text, byte),
+            _ if is_control(byte) => escape(&mut text, byte),
+            _ => text.push(byte as char),
+        }
+    }
+    Cow::Owned(text)
+}
+
+/// Binary data has NUL bytes, or mostly control characters, where text
+/// doesn't.
+pub fn looks_binary(header: &[u8]) -> bool {
+    let header = &header[..header.len().min(BINARY_CHECK_LEN)];
+    let control = header.iter()
+        .filter(|&&b| b < 0x20 && b != b'\n' && b != b'\r' && b != b'\t' && b != 0x1b)
+        .count();
+    header.contains(&0) || control * 10 > header.len()
+}
+
+/// Converts UTF-16 input into UTF-8, unpaired surrogates become U+FFFD.
+pub struct Utf16Reader<R> {
+    inner: R,
+    little_endian: bool,
+    decoded: Vec<u8>,
+    position: usize,
+    odd_byte: Option<u8>,
+    units: Vec<u16>,
+    started: bool,
+}
+
+impl<R: BufRead> Utf16Reader<R> {
+    pub fn new(inner: R, encoding: Encoding) -> Utf16Reader<R> {
+        Utf16Reader {
+            inner,
+            little_endian: encoding != Encoding::Utf16Be,
+            decoded: Vec::new(),
+            position: 0,
+            odd_byte: None,
+            units: Vec::new(),
+            started: false,
+        }
+    }
+
+    fn decode_units(&mut self, last: bool) {
+        let mut keep = 0;
+        if !last {
+            // A high surrogate waits for its pair in the next read.
+            if let Some(&unit) = self.units.last() {
+                if (0xd800..0xdc00).contains(&unit) {
+                    keep = 1;
+                }
+            }
+        }
+        // The byte order mark isn't part of the text.
+        let begin = if !self.started && self.units.first() == Some(&0xfeff) { 1 } else { 0 };
+        self.started = true;
+        let end = (self.units.len() - keep).max(begin);
+        let text = ::std::char::decode_utf16(self.units[begin..end].iter().cloned())
+            .map(|ch| ch.unwrap_or(::std::char::REPLACEMENT_CHARACTER))
+            .collect::<String>();
+        self.decoded = text.into_bytes();
+        self.position = 0;
+        self.units.drain(..end);
+    }
+}
+
+impl<R: BufRead> Read for Utf16Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decoded.len() {
+            let consumed = {
+                let bytes = self.inner.fill_buf()?;
+                if bytes.is_empty() {
+                    if self.units.is_empty() {
+                        return Ok(0);
+                    }
+                    self.decode_units(true);
+                    continue;
+                }
+                let mut bytes = bytes.iter().cloned();
+                let mut first = self.odd_byte.take().or_else(|| bytes.next());
+                while let Some(a) = first {
+                    match bytes.next() {
+                        Some(b) => {
+                            let unit = if self.little_endian {
+                                u16::from(a) | u16::from(b) << 8
+                            } else {
+                                u16::from(a) << 8 | u16::from(b)
+                            };
+                            self.units.push(unit);
+                        }
+                        None => self.odd_byte = Some(a),
+                    }
+                    first = bytes.next();
+                }
+                self.inner.fill_buf()?.len()
+            };
+            self.inner.consume(consumed);
+            self.decode_units(false);
+        }
+        let len = buf.len().min(self.decoded.len() - self.position);
+        buf[..len].copy_from_slice(&self.decoded[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[test]
+fn decode_invalid_utf8() {
+    assert_eq!(Encoding::Utf8.decode_line(b"plain"), Cow::Borrowed("plain"));
+    assert_eq!(Encoding::Utf8.decode_line(b"caf\xc3\xa9 \xff\xfeend"),
+               "caf\u{e9} \\xFF\\xFEend");
+    assert_eq!(Encoding::Utf8.decode_line(b"nul\x00 cut \xe2\x82"), "nul\\x00 cut \\xE2\\x82");
+    assert_eq!(Encoding::Utf8.decode_line(b"\tbell\x07\x1b[0m"), "\tbell\\x07\\x1B[0m");
+}
+
+#[test]
+fn decode_latin1_line() {
+    assert_eq!(Encoding::Latin1.decode_line(b"caf\xe9 \x85"), "caf\u{e9} \\x85");
+}
+
+#[test]
+fn read_utf16() {
+    let bytes = b"\xff\xfeh\x00\xe9\x00\n\x00=\xd8\x00\xde";
+    let mut text = String::new();
+    Utf16Reader::new(io::BufReader::with_capacity(3, &bytes[..]), Encoding::Utf16Le)
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text, "h\u{e9}\n\u{1f600}");
+
+    let bytes = b"\x00h\x00i";
+    let mut text = String::new();
+    Utf16Reader::new(&bytes[..], Encoding::Utf16Be).read_to_string(&mut text).unwrap();
+    assert_eq!(text, "hi");
+}
+
+#[test]
+fn detect_binary() {
+    assert!(!looks_binary(b"2017-05-02 INF started\n\tat main\n"));
+    assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00\x00\x00"));
+    assert!(looks_binary(b"\x01\x02\x03\x04 abcdef"));
+}
@@ -0,0 +1,462 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use regex::{self, Regex};
+use regex_syntax;
+
+/// Matcher built from a grep pattern.
+///
+/// A pattern is a single regex, unless its `MatchMode` reads expressions:
+/// regexes combined with `and`, `or`, `not` and parentheses, e.g.
+/// `(timeout or refused) and not retry`. Regexes containing spaces or
+/// parentheses are quoted in expressions, `"connection (reset|closed)"`.
+/// `!` negates a single regex as well as a part of an expression.
+#[derive(Clone, Debug)]
+pub enum Expression {
+    Regex(Regex),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+/// How letters of a pattern match their case.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Case {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the pattern has an uppercase letter.
+    Smart,
+}
+
+/// How patterns are turned into regexes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MatchMode {
+    pub case: Case,
+    /// Patterns are fixed strings, regex metacharacters match themselves.
+    pub literal: bool,
+    /// Grep patterns are expressions of regexes rather than a single one.
+    pub expression: bool,
+}
+
+impl MatchMode {
+    pub fn new() -> MatchMode {
+        MatchMode {
+            case: Case::Sensitive,
+            literal: false,
+            expression: false,
+        }
+    }
+
+    /// Applies a `-i`, `-s`, `-S`, `-F` or `-x` flag, returns whether
+    /// `flag` is one of them.
+    pub fn apply_flag(&mut self, flag: &str) -> bool {
+        match flag {
+            "-i" => self.case = Case::Insensitive,
+            "-s" => self.case = Case::Sensitive,
+            "-S" => self.case = Case::Smart,
+            "-F" => self.literal = true,
+            "-x" => self.expression = true,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Source of the regex matching `patern` in this mode.
+    pub fn source(&self, patern: &str) -> String {
+        let insensitive = match self.case {
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+            Case::Smart => !has_uppercase(patern, self.literal),
+        };
+        let source = if self.literal {
+            regex::escape(patern)
+        } else {
+            patern.to_string()
+        };
+        if insensitive {
+            format!("{}{}", INSENSITIVE_FLAG, source)
+        } else {
+            source
+        }
+    }
+}
+
+const INSENSITIVE_FLAG: &str = "(?i)";
+
+/// Letters escaped in a regex, like `\S` or `\W`, don't count.
+fn has_uppercase(patern: &str, literal: bool) -> bool {
+    let mut escaped = false;
+    for ch in patern.chars() {
+        if ch.is_uppercase() && !escaped {
+            return true;
+        }
+        escaped = !literal && ch == '\\' && !escaped;
+    }
+    false
+}
+
+impl Expression {
+    pub fn parse(text: &str, mode: MatchMode) -> Result<Expression, ParseError> {
+        if !mode.expression {
+            return match text.strip_prefix('!') {
+                Some(patern) => {
+                    Ok(Expression::Not(Box::new(regex(patern, mode, column(text, 1))?)))
+                }
+                None => regex(text, mode, 1),
+            };
+        }
+        let mut parser = Parser {
+            text,
+            mode,
+            tokens: tokenize(text)?,
+            next: 0,
+        };
+        let expression = parser.or()?;
+        match parser.tokens.get(parser.next) {
+            Some(&(ref token, position)) => {
+                Err(ParseError::new(position, format!("unexpected {}", token)))
+            }
+            None => Ok(expression),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match *self {
+            Expression::Regex(ref re) => re.is_match(text),
+            Expression::Not(ref expression) => !expression.matches(text),
+            Expression::And(ref left, ref right) => left.matches(text) && right.matches(text),
+            Expression::Or(ref left, ref right) => left.matches(text) || right.matches(text),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// Column of the error in the pattern, counted from 1.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: String) -> ParseError {
+        ParseError { position, message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.position)
+    }
+}
+
+/// Column of the byte at `idx` in `text`.
+fn column(text: &str, idx: usize) -> usize {
+    text[..idx].chars().count() + 1
+}
+
+/// Compiles a single regex, errors point into `patern`.
+pub fn compile(patern: &str, mode: MatchMode) -> Result<Regex, ParseError> {
+    let source = mode.source(patern);
+    // Errors of the regex crate are only text, so the syntax is checked on
+    // its own for where an error is.
+    let syntax_error = match regex_syntax::Parser::new().parse(&source) {
+        Ok(_) => None,
+        Err(regex_syntax::Error::Parse(err)) => {
+            Some((err.span().start.offset, err.kind().to_string()))
+        }
+        Err(regex_syntax::Error::Translate(err)) => {
+            Some((err.span().start.offset, err.kind().to_string()))
+        }
+        Err(err) => Some((0, err.to_string())),
+    };
+    if let Some((offset, reason)) = syntax_error {
+        // Offsets count the case flag in front of `patern` too.
+        let offset = if source.starts_with(INSENSITIVE_FLAG) {
+            offset.saturating_sub(INSENSITIVE_FLAG.len())
+        } else {
+            offset
+        };
+        let mut offset = offset.min(patern.len());
+        while !patern.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        return Err(ParseError::new(column(patern, offset),
+                                   format!("invalid regex: {}", one_line(&reason))));
+    }
+    Regex::new(&source)
+        .map_err(|err| ParseError::new(1, format!("invalid regex: {}", one_line(&err.to_string()))))
+}
+
+/// Last line of an error message, which is all that fits the message line.
+fn one_line(message: &str) -> &str {
+    let line = message.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or("");
+    let line = line.strip_prefix("error: ").unwrap_or(line);
+    line.trim_end_matches('.')
+}
+
+/// Compiles a regex starting at `position` of the whole pattern.
+fn regex(patern: &str, mode: MatchMode, position: usize) -> Result<Expression, ParseError> {
+    compile(patern, mode).map(Expression::Regex).map_err(|mut err| {
+        err.position += position - 1;
+        err
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Pattern(Cow<'a, str>),
+}
+
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+            Token::And => write!(f, "`and`"),
+            Token::Or => write!(f, "`or`"),
+            Token::Not => write!(f, "`not`"),
+            Token::Pattern(ref patern) => write!(f, "pattern {}", patern),
+        }
+    }
+}
+
+/// Tokens with their columns.
+fn tokenize(text: &str) -> Result<Vec<(Token<'_>, usize)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(begin, ch)) = chars.peek() {
+        let position = column(text, begin);
+        match ch {
+            _ if ch.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | '!' => {
+                chars.next();
+                tokens.push((match ch {
+                                 '(' => Token::Open,
+                                 ')' => Token::Close,
+                                 _ => Token::Not,
+                             },
+                             position));
+            }
+            '"' | '\'' => {
+                chars.next();
+                // Only the quote itself is escaped, other backslashes
+                // belong to the regex.
+                let mut patern = String::new();
+                let mut closed = false;
+                while let Some((_, next)) = chars.next() {
+                    if next == '\\' && chars.peek().map(|&(_, c)| c) == Some(ch) {
+                        patern.push(ch);
+                        chars.next();
+                    } else if next == ch {
+                        closed = true;
+                        break;
+                    } else {
+                        patern.push(next);
+                    }
+                }
+                if !closed {
+                    return Err(ParseError::new(position, "unterminated quote".to_string()));
+                }
+                tokens.push((Token::Pattern(Cow::Owned(patern)), position + 1));
+            }
+            _ => {
+                let mut end = text.len();
+                while let Some(&(idx, next)) = chars.peek() {
+                    if next.is_whitespace() || next == '(' || next == ')' {
+                        end = idx;
+                        break;
+                    }
+                    chars.next();
+                }
+                let token = match &text[begin..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    word => Token::Pattern(Cow::Borrowed(word)),
+                };
+                tokens.push((token, position));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or`, then `and`, then `not`, which binds the
+/// tightest.
+struct Parser<'a> {
+    text: &'a str,
+    mode: MatchMode,
+    tokens: Vec<(Token<'a>, usize)>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.text.chars().count() + 1, |&(_, pos)| pos)
+    }
+
+    fn or(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next += 1;
+            expression = Expression::And(Box::new(expression), Box::new(self.not()?));
+        }
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<Expression, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next += 1;
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        match self.tokens.get(self.next) {
+            Some(&(Token::Open, _)) => {
+                self.next += 1;
+                let expression = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(ParseError::new(self.position(), "expected `)`".to_string()));
+                }
+                self.next += 1;
+                Ok(expression)
+            }
+            Some(&(Token::Pattern(ref patern), _)) => {
+                self.next += 1;
+                regex(patern, self.mode, position)
+            }
+            Some((token, _)) => {
+                Err(ParseError::new(position, format!("expected a pattern, found {}", token)))
+            }
+            None => Err(ParseError::new(position, "expected a pattern".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+fn expression_mode() -> MatchMode {
+    let mut mode = MatchMode::new();
+    mode.apply_flag("-x");
+    mode
+}
+
+#[test]
+fn parse_expressions() {
+    let mode = expression_mode();
+    let expression = Expression::parse("(timeout or refused) and not retry", mode).unwrap();
+    assert!(expression.matches("connect timeout"));
+    assert!(expression.matches("connection refused"));
+    assert!(!expression.matches("timeout, retry 2"));
+    assert!(!expression.matches("connected"));
+
+    let expression = Expression::parse("\"GET (/api|/v2)\" or !'\\d{3} \\'ms\\''", mode)
+        .unwrap();
+    assert!(expression.matches("GET /api/users"));
+    assert!(expression.matches("no digits"));
+    assert!(!expression.matches("took 200 'ms'"));
+
+    let expression = Expression::parse("a or b and c", mode).unwrap();
+    assert!(expression.matches("a"));
+    assert!(!expression.matches("b"));
+}
+
+#[test]
+fn parse_plain_regexes() {
+    let mode = MatchMode::new();
+    assert!(Expression::parse("(ERR|WARN) db", mode).unwrap().matches("WARN db"));
+    assert!(Expression::parse("!healthcheck", mode).unwrap().matches("GET /users"));
+    assert!(!Expression::parse("!healthcheck", mode).unwrap().matches("GET /healthcheck"));
+
+    // Words of expressions are plain text without the `-x` flag.
+    let user = Expression::parse("user not found", mode).unwrap();
+    assert!(user.matches("GET /x: user not found"));
+    assert!(!user.matches("user found"));
+    let not_found = Expression::parse("not found", mode).unwrap();
+    assert!(!not_found.matches("x"));
+    assert!(not_found.matches("page not found"));
+    assert!(Expression::parse("cache and db or (disk)", mode)
+        .unwrap()
+        .matches("cache and db or disk"));
+    assert!(Expression::parse("'quoted' and", mode).unwrap().matches("'quoted' and more"));
+}
+
+#[test]
+fn parse_errors() {
+    let mode = expression_mode();
+    let error = |text| Expression::parse(text, mode).unwrap_err();
+
+    assert_eq!(error("(a or b and c").position, 14);
+    assert_eq!(error("(a or b and c").message, "expected `)`");
+    assert_eq!(error("a and or b").position, 7);
+    assert_eq!(error("a or b c").position, 8);
+    assert_eq!(error("a and \"b").position, 7);
+    assert_eq!(error("a and (b").position, 9);
+    assert_eq!(error("ok and b[").position, 9);
+    assert_eq!(error("x[").position, 2);
+    assert_eq!(error("!x[").position, 3);
+    assert_eq!(error("a or b)").message, "unexpected `)`");
+    assert_eq!(error("a or 'b(c' or d").position, 8);
+}
+
+#[test]
+fn compile_errors() {
+    let mode = MatchMode::new();
+    let err = compile("foo(", mode).unwrap_err();
+    assert_eq!(err.position, 4);
+    assert_eq!(err.to_string(), "invalid regex: unclosed group at column 4");
+    assert_eq!(compile("a)", mode).unwrap_err().position, 2);
+    assert_eq!(compile("zażółć(", mode).unwrap_err().position, 7);
+    assert_eq!(one_line("regex parse error:\n    a(\n     ^\nerror: unclosed group\n"),
+               "unclosed group");
+    assert!(compile("a|b", mode).is_ok());
+}
+
+#[test]
+fn match_modes() {
+    let mut mode = MatchMode::new();
+    let matches = |patern, mode, text| Expression::parse(patern, mode).unwrap().matches(text);
+    assert!(!matches("error", mode, "ERROR"));
+
+    assert!(mode.apply_flag("-i"));
+    assert!(matches("error", mode, "ERROR"));
+    assert!(matches("Error", mode, "ERROR"));
+
+    assert!(mode.apply_flag("-S"));
+    let mut expressions = mode;
+    assert!(expressions.apply_flag("-x"));
+    assert!(matches("error and not retry", expressions, "ERROR"));
+    assert!(!matches("Error", mode, "ERROR"));
+    assert!(matches(r"\Serror", mode, "XERROR"));
+
+    assert!(mode.apply_flag("-F"));
+    assert!(matches("at foo.Bar(", mode, "at foo.Bar(Bar.java:12)"));
+    assert!(!matches("foo.bar", mode, "fooxbar"));
+    assert!(!mode.apply_flag("-q"));
+
+    let mut mode = MatchMode::new();
+    mode.apply_flag("-i");
+    assert_eq!(compile("ab(", mode).unwrap_err().position, 3);
+}
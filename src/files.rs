@@ -0,0 +1,306 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use regex::Regex;
+
+use greps::*;
+use input;
+use merge;
+use options;
+
+/// An opened log, with its own stack of greps.
+pub struct LogFile {
+    pub name: String,
+    stream: input::LineStream,
+    pub greps: Greps,
+    /// Names of merged files, indexed by `Position::source`.
+    pub sources: Vec<String>,
+    // Lines are still being loaded, the search and the jump wait for them.
+    // Lines of the current grep before `pending_scanned` were looked at.
+    pending_search: Option<(String, Regex)>,
+    pending_line: Option<usize>,
+    pending_scanned: usize,
+}
+
+impl LogFile {
+    pub fn open(name: &str, options: &options::Options) -> LogFile {
+        let stream = input::open(name, options.follow, options.encoding);
+        LogFile::new(display_name(name), stream, Vec::new(), options)
+    }
+
+    /// The file after all of its rotated siblings, as one stream.
+    pub fn rotated(name: &str, options: &options::Options) -> LogFile {
+        if name == "-" {
+            return LogFile::open(name, options);
+        }
+        let family = input::rotated_family(Path::new(name));
+        if family.len() == 1 {
+            return LogFile::open(name, options);
+        }
+        let sources = family.iter()
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+            .collect();
+        LogFile::new(display_name(name), rotated_stream(&family, options), sources, options)
+    }
+
+    /// One view of all `names`, ordered by the timestamps of their lines.
+    pub fn merged(names: &[String], options: &options::Options) -> LogFile {
+        let streams = names.iter()
+            .map(|name| if options.rotated && name != "-" {
+                     rotated_stream(&input::rotated_family(Path::new(name)), options)
+                 } else {
+                     input::open(name, options.follow, options.encoding)
+                 })
+            .collect();
+        let sources = names.iter().map(|name| display_name(name)).collect::<Vec<_>>();
+        LogFile::new(sources.join(" + "), merge::merge(streams), sources, options)
+    }
+
+    fn new(name: String,
+           mut stream: input::LineStream,
+           sources: Vec<String>,
+           options: &options::Options)
+           -> LogFile {
+        let mut greps = Greps::new(stream.poll());
+        greps.mode = options.mode;
+        greps.wrap_search = options.wrap_search;
+        for (color, decoration) in &options.colors {
+            greps.decorations.insert(color.clone(), decoration.clone());
+        }
+        for pattern in &options.greps {
+            if let Err(err) = greps.new_grep(pattern) {
+                eprintln!("rustgrepper: invalid grep {}: {}", pattern, err);
+                process::exit(2);
+            }
+        }
+        let pending_search = options.search.as_ref().map(|pattern| {
+            match greps.search_regex(pattern) {
+                Ok((re, _)) => (pattern.clone(), re),
+                Err(err) => {
+                    eprintln!("rustgrepper: invalid search {}: {}", pattern, err);
+                    process::exit(2);
+                }
+            }
+        });
+        LogFile {
+            name,
+            stream,
+            greps,
+            sources,
+            pending_search,
+            pending_line: options.line,
+            pending_scanned: 0,
+        }
+    }
+
+    /// Picks up lines loaded in the background, returns whether anything
+    /// changed.
+    pub fn poll(&mut self) -> bool {
+        let chunks = self.stream.poll();
+        let mut changed = chunks.iter().any(|chunk| !chunk.is_empty());
+        if changed {
+            self.greps.append_lines(chunks);
+        }
+        if self.pending_line.is_none() && self.pending_search.is_none() {
+            return changed;
+        }
+        // Only lines that arrived since the last poll are looked at.
+        let greps = &self.greps;
+        let lines_count = greps.lines_count();
+        let from = self.pending_scanned.min(lines_count);
+        // The line is looked for in the file of the line on top.
+        let source = greps.top_source();
+        let found_line = self.pending_line
+            .and_then(|number| greps.find_line(source, number, from));
+        let found_search = self.pending_search.as_ref().is_some_and(|(_, re)| {
+            (from..lines_count).any(|idx| re.is_match(greps.text(idx)))
+        });
+        self.pending_scanned = lines_count;
+        if let Some(index) = found_line {
+            self.greps.change_current_line_index(index);
+            self.pending_line = None;
+            changed = true;
+        }
+        if found_search {
+            if let Some((pattern, _)) = self.pending_search.take() {
+                // The pattern was checked when the file was opened.
+                let _ = self.greps.apply_search_patern(&pattern);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.stream.is_binary()
+    }
+}
+
+/// Only the newest file of a rotated family can still grow.
+fn rotated_stream(family: &[PathBuf], options: &options::Options) -> input::LineStream {
+    let last = family.len() - 1;
+    let streams = family.iter()
+        .enumerate()
+        .map(|(idx, path)| {
+                 input::open(&path.to_string_lossy(),
+                             options.follow && idx == last,
+                             options.encoding)
+             })
+        .collect();
+    input::concat(streams)
+}
+
+fn display_name(name: &str) -> String {
+    if name == "-" {
+        "stdin".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+pub struct LogFiles {
+    pub files: Vec<LogFile>,
+    pub selected: usize,
+}
+
+impl LogFiles {
+    pub fn open(options: &options::Options) -> LogFiles {
+        let mut files = if options.merge && options.files.len() > 1 {
+            vec![LogFile::merged(&options.files, options)]
+        } else {
+            options.files
+                .iter()
+                .map(|name| if options.rotated {
+                         LogFile::rotated(name, options)
+                     } else {
+                         LogFile::open(name, options)
+                     })
+                .collect::<Vec<_>>()
+        };
+        if files.is_empty() {
+            files.push(LogFile::open("-", options));
+        }
+        LogFiles {
+            files,
+            selected: 0,
+        }
+    }
+
+    pub fn current_file(&self) -> &LogFile {
+        &self.files[self.selected]
+    }
+
+    pub fn current_file_mut(&mut self) -> &mut LogFile {
+        &mut self.files[self.selected]
+    }
+
+    /// Opens one more file and selects it.
+    pub fn add_file(&mut self, name: &str, options: &options::Options) -> io::Result<()> {
+        let stream = input::try_open(name, options.follow, options.encoding)?;
+        self.files.push(LogFile::new(display_name(name), stream, Vec::new(), options));
+        self.selected = self.files.len() - 1;
+        Ok(())
+    }
+
+    pub fn select_next_file(&mut self) {
+        self.selected = (self.selected + 1) % self.files.len();
+    }
+
+    pub fn select_prev_file(&mut self) {
+        self.selected = (self.selected + self.files.len() - 1) % self.files.len();
+    }
+
+    /// Name of the active file for the status bar, empty when a single text
+    /// file is open.
+    pub fn label(&self) -> String {
+        let file = self.current_file();
+        let mut label = if self.files.len() > 1 {
+            format!("[{}/{}] {}", self.selected + 1, self.files.len(), file.name)
+        } else if !file.sources.is_empty() {
+            file.name.clone()
+        } else {
+            String::new()
+        };
+        if file.is_binary() {
+            if !label.is_empty() {
+                label.push(' ');
+            }
+            label.push_str("(binary file)");
+        }
+        label
+    }
+}
+
+#[cfg(test)]
+fn poll_until(file: &mut LogFile, lines: usize) {
+    for _ in 0..200 {
+        file.poll();
+        if file.greps.root_lines_count() >= lines {
+            return;
+        }
+        ::std::thread::sleep(::std::time::Duration::from_millis(5));
+    }
+    panic!("lines didn't arrive");
+}
+
+#[test]
+fn pending_line_and_search() {
+    let (feed, chunks) = ::std::sync::mpsc::channel::<input::Chunk>();
+    let stream = input::LineStream::spawn(move |sender| for chunk in chunks {
+        if sender.send(chunk).is_err() {
+            return;
+        }
+    });
+    let numbered = |text: &str, first: usize| {
+        let mut chunk = input::Chunk::from(text);
+        chunk.number = first;
+        chunk
+    };
+    let mut options = options::Options::new();
+    options.greps = vec!["ERR".to_string()];
+    options.line = Some(3);
+    options.search = Some("db".to_string());
+    let mut file = LogFile::new("test".to_string(), stream, Vec::new(), &options);
+
+    feed.send(numbered("INF a\nERR b\nINF c\n", 1)).unwrap();
+    poll_until(&mut file, 3);
+    assert!(file.pending_line.is_some() && file.pending_search.is_some());
+    assert_eq!(file.greps.current_grep().line_index, 0);
+
+    // Line 3 isn't in the grep, the jump goes to the match after it.
+    feed.send(numbered("ERR db d\nERR e\n", 4)).unwrap();
+    poll_until(&mut file, 5);
+    assert!(file.pending_line.is_none() && file.pending_search.is_none());
+    assert_eq!(file.greps.current_grep().line_index, 1);
+    assert_eq!(file.greps.search_position(), Some((1, 1)));
+}
+
+#[test]
+fn open_merged_files() {
+    use std::fs;
+
+    let dir = ::utils::TempDir::new("merge");
+    let client = dir.join("client.log").to_string_lossy().into_owned();
+    let server = dir.join("server.log").to_string_lossy().into_owned();
+    fs::write(&client, "10:00:01 client a\n10:00:04 client b\n").unwrap();
+    fs::write(&server, "10:00:02 server a\n10:00:03 server b\n").unwrap();
+    let mut options = options::Options::new();
+    options.files = vec![client.clone(), server.clone()];
+    options.merge = true;
+
+    let mut files = LogFiles::open(&options);
+    assert_eq!(files.files.len(), 1);
+    poll_until(files.current_file_mut(), 4);
+    let greps = &files.current_file().greps;
+    let lines = (0..greps.lines_count())
+        .map(|idx| (greps.text(idx), greps.position(idx).source))
+        .collect::<Vec<_>>();
+    assert_eq!(lines,
+               vec![("10:00:01 client a", 0),
+                    ("10:00:02 server a", 1),
+                    ("10:00:03 server b", 1),
+                    ("10:00:04 client b", 0)]);
+    assert_eq!(files.current_file().sources, vec![client.clone(), server.clone()]);
+    assert_eq!(files.label(), format!("{} + {}", client, server));
+}
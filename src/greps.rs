@@ -1,35 +1,164 @@
 use regex::Regex;
+use expression::{self, Expression, MatchMode, ParseError};
+use index::{Chunk, LineIndex, Position};
 use utils;
 
 use std::collections::HashMap;
 
-pub struct Grep<'a> {
+pub struct Grep {
     pub patern: String,
     pub line_index: usize,
     search_lines_idxs: Vec<usize>,
-    pub lines: Vec<utils::Line<'a>>,
+    /// Regex of the search `search_lines_idxs` matched, lines appended later
+    /// are matched against it too.
+    search: Option<Regex>,
+    /// Picks lines of the parent grep, `None` for ROOT.
+    filter: Option<Expression>,
+    /// Lines of context taken before and after each match.
+    context: (usize, usize),
+    /// Index of the grep this one was derived from, `None` for ROOT and for
+    /// greps whose parent was closed.
+    parent: Option<usize>,
+    /// Index in ROOT of each line of the grep, empty for ROOT itself.
+    origins: Vec<usize>,
+    // How far lines of the parent were filtered, the after context still
+    // missing and the parent line taken last.
+    scanned: usize,
+    after_left: usize,
+    last_taken: Option<usize>,
 }
 
-pub struct Greps<'a> {
-    pub greps: Vec<Grep<'a>>,
+impl Grep {
+    fn new(patern: String,
+           filter: Option<Expression>,
+           context: (usize, usize),
+           parent: Option<usize>)
+           -> Grep {
+        Grep {
+            patern,
+            line_index: 0,
+            search_lines_idxs: Vec::new(),
+            search: None,
+            filter,
+            context,
+            parent,
+            origins: Vec::new(),
+            scanned: 0,
+            after_left: 0,
+            last_taken: None,
+        }
+    }
+
+    fn len(&self, root: &LineIndex) -> usize {
+        if self.filter.is_none() {
+            root.len()
+        } else {
+            self.origins.len()
+        }
+    }
+
+    /// Adds lines from `from` on that match the search to its matches.
+    fn search_new_lines(&mut self, root: &LineIndex, from: usize) {
+        if let Some(ref re) = self.search {
+            let matches = (from..self.len(root))
+                .filter(|&idx| re.is_match(root.text(self.origin(idx))))
+                .collect::<Vec<_>>();
+            self.search_lines_idxs.extend(matches);
+        }
+    }
+
+    /// Picks matches and their context from lines the parent got since the
+    /// last call.
+    fn filter_parent(&mut self, parent: &Grep, root: &LineIndex) {
+        let filter = match self.filter {
+            Some(ref filter) => filter.clone(),
+            None => return,
+        };
+        let (before, after) = self.context;
+        let parent_len = parent.len(root);
+        for idx in self.scanned..parent_len {
+            if filter.matches(root.text(parent.origin(idx))) {
+                let first = self.last_taken
+                    .map_or(0, |last| last + 1)
+                    .max(idx.saturating_sub(before));
+                for context_idx in first..idx {
+                    self.take(parent, context_idx);
+                }
+                self.take(parent, idx);
+                self.after_left = after;
+            } else if self.after_left > 0 {
+                self.take(parent, idx);
+                self.after_left -= 1;
+            }
+        }
+        self.scanned = parent_len;
+    }
+
+    fn take(&mut self, parent: &Grep, idx: usize) {
+        self.origins.push(parent.origin(idx));
+        self.last_taken = Some(idx);
+    }
+
+    fn origin(&self, index: usize) -> usize {
+        self.origins.get(index).cloned().unwrap_or(index)
+    }
+
+    /// Index of the line that is `origin` in ROOT, or of the closest line
+    /// after it.
+    fn index_of(&self, origin: usize, root: &LineIndex) -> usize {
+        let index = if self.filter.is_none() {
+            origin
+        } else {
+            match self.origins.binary_search(&origin) {
+                Ok(index) | Err(index) => index,
+            }
+        };
+        index.min(self.len(root).saturating_sub(1))
+    }
+}
+
+/// Where `next_search` and `prev_search` went.
+#[derive(Debug, PartialEq)]
+pub enum SearchStep {
+    Moved,
+    /// Went past the last match to the first one, or the other way round.
+    Wrapped,
+    /// There is no match further in that direction.
+    Stopped,
+    NoMatches,
+}
+
+pub struct Greps {
+    /// Lines of ROOT, the other greps keep indexes into it.
+    root: LineIndex,
+    pub greps: Vec<Grep>,
     current_search_pattern: String,
     pub decorations: HashMap<String, utils::DecorationPattern>,
     pub selected: usize,
+    /// Case and literal matching of patterns without flags of their own.
+    pub mode: MatchMode,
+    /// Stepping past the last search match goes back to the first one.
+    pub wrap_search: bool,
+    /// Decoration of a search still being typed.
+    preview_pattern: Option<String>,
 }
 
-impl<'a> Greps<'a> {
-    pub fn new(lines: Vec<utils::Line<'a>>) -> Self {
-        let greps = vec![Grep {
-                             patern: "ROOT".to_string(),
-                             line_index: 0,
-                             search_lines_idxs: Vec::new(),
-                             lines: lines,
-                         }];
+impl Greps {
+    pub fn new(chunks: Vec<Chunk>) -> Self {
+        let mut root = LineIndex::new();
+        for chunk in chunks {
+            root.append(chunk);
+        }
+        let greps = vec![Grep::new("ROOT".to_string(), None, (0, 0), None)];
         Greps {
-            greps: greps,
+            root,
+            greps,
             current_search_pattern: "".to_string(),
             decorations: HashMap::new(),
             selected: 0,
+            mode: MatchMode::new(),
+            wrap_search: true,
+            preview_pattern: None,
         }
     }
 
@@ -37,120 +166,555 @@ impl<'a> Greps<'a> {
         &self.greps[self.selected]
     }
 
+    /// Number of lines in the current grep.
+    pub fn lines_count(&self) -> usize {
+        self.current_grep().len(&self.root)
+    }
+
+    /// Number of lines read so far, all of them are in ROOT.
+    #[cfg(test)]
+    pub fn root_lines_count(&self) -> usize {
+        self.root.len()
+    }
+
+    /// Text of line `index` of the current grep.
+    pub fn text(&self, index: usize) -> &'static str {
+        self.root.text(self.current_grep().origin(index))
+    }
+
+    /// Where line `index` of the current grep is in its file.
+    pub fn position(&self, index: usize) -> Position {
+        self.root.position(self.current_grep().origin(index))
+    }
+
+    /// Line `index` of the current grep, as it is shown.
+    pub fn line(&self, index: usize) -> utils::Line<'static> {
+        self.grep_line(self.selected, index)
+    }
+
+    /// A line of a grep with context is context when it doesn't match the
+    /// filter, and it follows a gap when lines of the file right before it
+    /// were left out.
+    fn grep_line(&self, grep: usize, index: usize) -> utils::Line<'static> {
+        let grep = &self.greps[grep];
+        let origin = grep.origin(index);
+        let buffer = self.root.text(origin);
+        let mut line = utils::Line::from(buffer);
+        if grep.context != (0, 0) {
+            line.context = grep.filter.as_ref().is_some_and(|filter| !filter.matches(buffer));
+            line.after_gap = index > 0 && origin > grep.origin(index - 1) + 1;
+        }
+        line
+    }
+
+    /// Indexes past the last line, like a page down near the end, stop at
+    /// it.
     pub fn change_current_line_index(&mut self, index: usize) {
-        self.greps[self.selected].line_index = index;
-    }
-
-    pub fn apply_search_patern(&mut self, pattern: &str) {
-        if let Ok(re) = Regex::new(&pattern) {
-            let search_lines_idxs = self.greps[self.selected]
-                .lines
-                .iter()
-                .enumerate()
-                .filter(|&(_, ref l)| re.is_match(l.buffer))
-                .map(|(idx, _)| idx)
-                .collect::<Vec<usize>>();
-
-            if search_lines_idxs.len() > 0 {
-                self.greps[self.selected].search_lines_idxs = search_lines_idxs;
-                self.greps[self.selected].line_index = self.greps[self.selected].search_lines_idxs
-                    [0];
-                self.decorations.remove(&self.current_search_pattern);
-                self.current_search_pattern = pattern.to_string();
-                self.decorations
+        let last = self.lines_count().saturating_sub(1);
+        self.greps[self.selected].line_index = index.min(last);
+    }
+
+    /// Index of the first line from `from` on of the file `source` numbered
+    /// `number` or more.
+    pub fn find_line(&self, source: usize, number: usize, from: usize) -> Option<usize> {
+        (from..self.lines_count()).find(|&idx| {
+            let position = self.position(idx);
+            position.source == source && position.number >= number
+        })
+    }
+
+    /// Source of the line on top, of the first one when there are none yet.
+    pub fn top_source(&self) -> usize {
+        let index = self.current_grep().line_index;
+        if index < self.lines_count() {
+            self.position(index).source
+        } else {
+            0
+        }
+    }
+
+    /// Regex of a search `pattern`, which may start with match mode flags,
+    /// like `-i`, along with its source.
+    pub fn search_regex(&self, pattern: &str) -> Result<(Regex, String), ParseError> {
+        let (_, mode, rest) = parse_options(pattern, self.mode);
+        let re = expression::compile(rest, mode).map_err(|err| shift(err, pattern, rest))?;
+        Ok((re, mode.source(rest)))
+    }
+
+    /// Moves to the first match from the line on top, or from the beginning
+    /// when there is none after it. Returns how many lines matched.
+    pub fn apply_search_patern(&mut self, pattern: &str) -> Result<usize, ParseError> {
+        let (re, source) = self.search_regex(pattern)?;
+        let search_lines_idxs = (0..self.lines_count())
+            .filter(|&idx| re.is_match(self.text(idx)))
+            .collect::<Vec<usize>>();
+
+        let matches = search_lines_idxs.len();
+        if matches > 0 {
+            let grep = &mut self.greps[self.selected];
+            let next = match search_lines_idxs.binary_search(&grep.line_index) {
+                Ok(idx) | Err(idx) => search_lines_idxs.get(idx).unwrap_or(&search_lines_idxs[0]),
+            };
+            grep.line_index = *next;
+            grep.search_lines_idxs = search_lines_idxs;
+            grep.search = Some(re);
+            self.decorations.remove(&self.current_search_pattern);
+            // Highlighted the way it was matched.
+            self.current_search_pattern = source;
+            self.decorations
                 .insert(self.current_search_pattern.clone(),
                         utils::DecorationPattern::from_single_attr(utils::Attribute::Inverse,
                                                                    &self.current_search_pattern));
+        }
+        Ok(matches)
+    }
+
+    /// Highlights matches of a search while it is typed, and moves from line
+    /// `from` to the first match after it, unless there is one among the
+    /// `visible` lines already. Patterns that don't compile yet are ignored.
+    pub fn preview_search(&mut self, pattern: &str, from: usize, visible: usize) {
+        self.end_preview();
+        self.change_current_line_index(from);
+        let (re, source) = match self.search_regex(pattern) {
+            Ok(search) if !pattern.is_empty() => search,
+            _ => return,
+        };
+        let lines_count = self.lines_count();
+        let from = from.min(lines_count);
+        let visible_end = (from + visible).min(lines_count);
+        if !(from..visible_end).any(|idx| re.is_match(self.text(idx))) {
+            let found = (visible_end..lines_count)
+                .chain(0..from)
+                .find(|&idx| re.is_match(self.text(idx)));
+            if let Some(idx) = found {
+                self.change_current_line_index(idx);
+            }
+        }
+        if !self.decorations.contains_key(&source) {
+            let decoration = utils::DecorationPattern::from_single_attr(utils::Attribute::Inverse,
+                                                                        &source);
+            self.decorations.insert(source.clone(), decoration);
+            self.preview_pattern = Some(source);
+        }
+    }
+
+    /// Drops the highlight of a search that was being typed.
+    pub fn end_preview(&mut self) {
+        if let Some(source) = self.preview_pattern.take() {
+            self.decorations.remove(&source);
+        }
+    }
+
+    /// Appends lines that arrived after the pager started to the ROOT grep,
+    /// every derived grep picks the ones matching its pattern from its
+    /// parent.
+    pub fn append_lines(&mut self, chunks: Vec<Chunk>) {
+        let from = self.root.len();
+        for chunk in chunks {
+            self.root.append(chunk);
+        }
+        let root = &self.root;
+        self.greps[0].search_new_lines(root, from);
+        // Parents always come before the greps derived from them.
+        for idx in 1..self.greps.len() {
+            let (parents, greps) = self.greps.split_at_mut(idx);
+            if let Some(parent) = greps[0].parent {
+                let from = greps[0].len(root);
+                greps[0].filter_parent(&parents[parent], root);
+                greps[0].search_new_lines(root, from);
             }
         }
     }
 
     pub fn decorations(&self) -> Vec<utils::DecorationPattern> {
-        self.decorations
-            .clone()
-            .into_iter()
-            .map(|(_, v)| v)
-            .collect()
-    }
-
-    pub fn new_grep(&mut self, patern: &str) {
-        if let Ok(re) = Regex::new(&patern) {
-            let cur_patern = self.current_grep().patern.clone();
-            let new_lines = self.greps[self.selected]
-                .lines
-                .clone()
-                .into_iter()
-                .filter(|l| re.is_match(l.buffer))
-                .map(|l| l)
-                .collect();
-
-            self.greps.push(Grep {
-                                patern: cur_patern + " > " + patern,
-                                line_index: 0,
-                                search_lines_idxs: Vec::new(),
-                                lines: new_lines,
-                            });
-            self.selected = self.greps.len() - 1;
-        }
-    }
-
-    pub fn modify_search<F>(&mut self, modifier: F)
-        where F: Fn(usize) -> usize
+        self.decorations.values().cloned().collect()
+    }
+
+    /// `patern` is an `Expression`, it may start with grep like context
+    /// options and match mode flags, e.g. `-C 3 ERR` or
+    /// `-B2 -A5 -i -x timeout or refused`.
+    pub fn new_grep(&mut self, patern: &str) -> Result<(), ParseError> {
+        let (context, mode, filter) = parse_options(patern, self.mode);
+        let filter = Expression::parse(filter, mode).map_err(|err| shift(err, patern, filter))?;
+        let name = self.current_grep().patern.clone() + " > " + patern;
+        let mut grep = Grep::new(name, Some(filter), context, Some(self.selected));
+        grep.filter_parent(self.current_grep(), &self.root);
+        self.greps.push(grep);
+        self.selected = self.greps.len() - 1;
+        Ok(())
+    }
+
+    /// Moves from the match on top to the one `modifier` picks by its
+    /// index, or `None` at either end. Away from matches it goes to the
+    /// closest one.
+    pub fn modify_search<F>(&mut self, modifier: F) -> SearchStep
+        where F: Fn(usize) -> Option<usize>
     {
-        let search_lines_idxs = self.greps[self.selected].search_lines_idxs.clone();
-        let current_line_idx = self.greps[self.selected].line_index;
-
-        match utils::find_closest_index(&search_lines_idxs, current_line_idx) {
-            Some(found_idx) => {
-                if found_idx == current_line_idx {
-                    match search_lines_idxs.binary_search(&found_idx) {
-                        Ok(idx) => {
-                            match search_lines_idxs.get(modifier(idx)) {
-                                Some(_) => {
-                                    self.greps[self.selected].line_index = search_lines_idxs
-                                        [modifier(idx)]
-                                }
-                                None => {}
-                            }
-                        }
-                        Err(_) => {}
-                    }
-                } else {
-                    self.greps[self.selected].line_index = found_idx;
-                }
-            }
-            None => {}
+        let grep = &mut self.greps[self.selected];
+        let count = grep.search_lines_idxs.len();
+        let found_idx = match utils::find_closest_index(&grep.search_lines_idxs, grep.line_index) {
+            Some(found_idx) => found_idx,
+            None => return SearchStep::NoMatches,
+        };
+        if found_idx != grep.line_index {
+            grep.line_index = found_idx;
+            return SearchStep::Moved;
         }
+        let idx = grep.search_lines_idxs.binary_search(&found_idx).unwrap_or(0);
+        let (next_idx, step) = match modifier(idx) {
+            Some(next_idx) if next_idx < count => (next_idx, SearchStep::Moved),
+            _ if !self.wrap_search || count == 1 => return SearchStep::Stopped,
+            _ if idx == 0 => (count - 1, SearchStep::Wrapped),
+            _ => (0, SearchStep::Wrapped),
+        };
+        grep.line_index = grep.search_lines_idxs[next_idx];
+        step
+    }
+
+    pub fn next_search(&mut self) -> SearchStep {
+        self.modify_search(|searches_idx| Some(searches_idx + 1))
     }
 
-    pub fn next_search(&mut self) {
-        self.modify_search(|searches_idx| searches_idx + 1);
+    pub fn prev_search(&mut self) -> SearchStep {
+        self.modify_search(|searches_idx| searches_idx.checked_sub(1))
     }
 
-    pub fn prev_search(&mut self) {
-        self.modify_search(|searches_idx| if searches_idx != 0 {
-                               searches_idx - 1
-                           } else {
-                               searches_idx
-                           });
+    /// How many search matches there are in the current grep up to the line
+    /// on top, and in total.
+    pub fn search_position(&self) -> Option<(usize, usize)> {
+        let grep = self.current_grep();
+        if grep.search_lines_idxs.is_empty() {
+            return None;
+        }
+        let position = match grep.search_lines_idxs.binary_search(&grep.line_index) {
+            Ok(idx) => idx + 1,
+            Err(idx) => idx,
+        };
+        Some((position, grep.search_lines_idxs.len()))
     }
 
     pub fn select_one_to_left(&mut self) {
         if self.selected >= 1 {
-            self.selected = self.selected - 1;
+            self.selected -= 1;
         }
     }
     pub fn select_one_to_right(&mut self) {
         if self.selected < self.greps.len() - 1 {
-            self.selected = self.selected + 1;
+            self.selected += 1;
         }
     }
 
+    /// Selects the grep this one was derived from, at the line shown on top
+    /// of this one.
+    pub fn select_parent(&mut self) {
+        let parent = self.current_grep().parent.unwrap_or(0);
+        self.select_with_same_line(parent);
+    }
+
+    pub fn select_root(&mut self) {
+        self.select_with_same_line(0);
+    }
+
+    fn select_with_same_line(&mut self, target: usize) {
+        let lines_count = self.lines_count();
+        if lines_count == 0 {
+            self.selected = target;
+            return;
+        }
+        let grep = self.current_grep();
+        let origin = grep.origin(grep.line_index.min(lines_count - 1));
+        self.selected = target;
+        let index = self.greps[target].index_of(origin, &self.root);
+        self.change_current_line_index(index);
+    }
+
+    /// Greps derived from the closed one keep the lines they have, but pick
+    /// no more, there is nothing left to pick them from.
     pub fn close_grep(&mut self) {
         if self.selected != 0 {
             let curent = self.selected;
-            self.selected = self.selected - 1;
+            self.selected -= 1;
             self.greps.remove(curent);
+            for grep in &mut self.greps {
+                grep.parent = match grep.parent {
+                    Some(parent) if parent == curent => None,
+                    Some(parent) if parent > curent => Some(parent - 1),
+                    parent => parent,
+                };
+            }
         }
     }
 }
+
+/// Splits leading `-A N`, `-B N` and `-C N` context options and `-i`,
+/// `-s`, `-S`, `-F` and `-x` match mode flags off `patern`, `--` ends them.
+fn parse_options(patern: &str, mode: MatchMode) -> ((usize, usize), MatchMode, &str) {
+    let option = Regex::new(r"^(?:-([ABC]) ?(\d+)|(-[isSFx]))(?: +|$)").unwrap();
+    let mut context = (0, 0);
+    let mut mode = mode;
+    let mut rest = patern;
+    while let Some(caps) = option.captures(rest) {
+        match caps.get(3) {
+            Some(flag) => {
+                mode.apply_flag(flag.as_str());
+            }
+            None => {
+                let count = caps[2].parse().unwrap_or(0);
+                match &caps[1] {
+                    "A" => context.1 = count,
+                    "B" => context.0 = count,
+                    _ => context = (count, count),
+                }
+            }
+        }
+        rest = &rest[caps[0].len()..];
+    }
+    if rest.starts_with("-- ") {
+        rest = &rest[3..];
+    }
+    (context, mode, rest)
+}
+
+/// Makes an error in `rest`, the end of `patern`, point into `patern`.
+fn shift(mut err: ParseError, patern: &str, rest: &str) -> ParseError {
+    err.position += patern[..patern.len() - rest.len()].chars().count();
+    err
+}
+
+#[cfg(test)]
+fn lines(greps: &Greps, grep: usize) -> Vec<&'static str> {
+    (0..greps.greps[grep].len(&greps.root)).map(|idx| greps.grep_line(grep, idx).buffer).collect()
+}
+
+#[test]
+fn append_lines_refilters_derived_greps() {
+    let mut greps = Greps::new(vec![Chunk::from("ERR a\nINF b")]);
+    greps.new_grep("ERR").unwrap();
+    greps.append_lines(vec![Chunk::from("INF c\nERR d")]);
+
+    assert_eq!(lines(&greps, 0).len(), 4);
+    assert_eq!(lines(&greps, 1), vec!["ERR a", "ERR d"]);
+}
+
+#[test]
+fn append_lines_extends_search_matches() {
+    let mut greps = Greps::new(vec![Chunk::from("ERR db a\nINF b\nERR c")]);
+    greps.new_grep("ERR").unwrap();
+    assert_eq!(greps.apply_search_patern("db").unwrap(), 1);
+    greps.append_lines(vec![Chunk::from("INF db x\nERR db d")]);
+
+    assert_eq!(greps.search_position(), Some((1, 2)));
+    assert_eq!(greps.next_search(), SearchStep::Moved);
+    assert_eq!(greps.text(greps.current_grep().line_index), "ERR db d");
+    greps.select_root();
+    assert_eq!(greps.search_position(), None);
+}
+
+#[test]
+fn line_index_stays_on_lines() {
+    let mut greps = Greps::new(vec![Chunk::from("a\nb\nc")]);
+    greps.change_current_line_index(50);
+    assert_eq!(greps.current_grep().line_index, 2);
+    greps.new_grep("x").unwrap();
+    greps.change_current_line_index(3);
+    assert_eq!(greps.current_grep().line_index, 0);
+}
+
+#[test]
+fn select_parent_keeps_line() {
+    let mut greps = Greps::new(vec![Chunk::from("ERR db a\nINF b\nERR c\nERR db d\nINF e")]);
+    greps.new_grep("ERR").unwrap();
+    greps.new_grep("db").unwrap();
+    greps.append_lines(vec![Chunk::from("ERR db f")]);
+    greps.change_current_line_index(1);
+
+    greps.select_parent();
+    assert_eq!(greps.selected, 1);
+    assert_eq!(greps.text(greps.current_grep().line_index), "ERR db d");
+
+    greps.select_one_to_right();
+    greps.change_current_line_index(2);
+    greps.select_root();
+    assert_eq!(greps.selected, 0);
+    assert_eq!(greps.current_grep().line_index, 5);
+}
+
+#[test]
+fn find_line_of_source() {
+    let mut greps = Greps::new(vec![Chunk::from("a 1\nb 1\n")]);
+    let mut chunk = Chunk::from("a 2\nb 2\nc 2");
+    chunk.source = 1;
+    greps.append_lines(vec![chunk, Chunk::from("c 1")]);
+
+    assert_eq!(greps.find_line(0, 2, 0), Some(1));
+    assert_eq!(greps.find_line(1, 2, 0), Some(3));
+    assert_eq!(greps.find_line(0, 3, 0), None);
+    assert_eq!(greps.top_source(), 0);
+    greps.change_current_line_index(4);
+    assert_eq!(greps.top_source(), 1);
+
+    greps.new_grep("c").unwrap();
+    assert_eq!(greps.find_line(0, 1, 0), Some(1));
+    assert_eq!(greps.find_line(1, 1, 0), Some(0));
+}
+
+#[test]
+fn grep_with_context() {
+    let mode = MatchMode::new();
+    assert_eq!(parse_options("-C 2 ERR", mode), ((2, 2), mode, "ERR"));
+    assert_eq!(parse_options("-B1 -A3 a b", mode), ((1, 3), mode, "a b"));
+    assert_eq!(parse_options("-- -A1", mode), ((0, 0), mode, "-A1"));
+    assert_eq!(parse_options("-Ax", mode), ((0, 0), mode, "-Ax"));
+
+    let mut greps = Greps::new(vec![Chunk::from("a\nb\nERR 1\nc\nd\ne\nf\nERR 2\ng")]);
+    greps.new_grep("-B1 -A1 ERR").unwrap();
+    greps.append_lines(vec![Chunk::from("h\ni\nERR 3")]);
+    greps.append_lines(vec![Chunk::from("j\nk")]);
+
+    let lines = (0..greps.lines_count())
+        .map(|idx| greps.line(idx))
+        .map(|l| (l.buffer, l.context, l.after_gap))
+        .collect::<Vec<_>>();
+    assert_eq!(lines,
+               vec![("b", true, false),
+                    ("ERR 1", false, false),
+                    ("c", true, false),
+                    ("f", true, true),
+                    ("ERR 2", false, false),
+                    ("g", true, false),
+                    ("i", true, true),
+                    ("ERR 3", false, false),
+                    ("j", true, false)]);
+
+    greps.change_current_line_index(4);
+    greps.select_root();
+    assert_eq!(greps.current_grep().line_index, 7);
+}
+
+#[test]
+fn inverted_grep() {
+    let mut greps = Greps::new(vec![Chunk::from("GET /healthcheck\nERR db\nGET /users")]);
+    greps.new_grep("!healthcheck").unwrap();
+    greps.append_lines(vec![Chunk::from("GET /healthcheck\n!bang")]);
+
+    assert_eq!(greps.current_grep().patern, "ROOT > !healthcheck");
+    assert_eq!(lines(&greps, greps.selected), vec!["ERR db", "GET /users", "!bang"]);
+
+    greps.new_grep("[!]").unwrap();
+    assert_eq!(greps.lines_count(), 1);
+}
+
+#[test]
+fn grep_expression() {
+    let mut greps = Greps::new(vec![Chunk::from("timeout\nrefused, retry\nok\nrefused")]);
+    greps.new_grep("-x (timeout or refused) and not retry").unwrap();
+
+    assert_eq!(lines(&greps, greps.selected), vec!["timeout", "refused"]);
+
+    let err = greps.new_grep("-C 1 -x (a or b").unwrap_err();
+    assert_eq!(err.position, 16);
+    assert_eq!(greps.greps.len(), 2);
+
+    // Without `-x` the words are part of the regex.
+    greps.new_grep("not retry").unwrap();
+    assert_eq!(greps.lines_count(), 0);
+}
+
+#[test]
+fn grep_match_modes() {
+    let text = Chunk::from("at Foo.run(Foo.java)\nat foo.run\nERROR x");
+    let mut greps = Greps::new(vec![text]);
+    greps.mode.apply_flag("-S");
+
+    assert_eq!(greps.apply_search_patern("error"), Ok(1));
+    assert_eq!(greps.apply_search_patern("-s error"), Ok(0));
+    assert_eq!(greps.apply_search_patern("-i -F foo.run("), Ok(1));
+    assert_eq!(greps.apply_search_patern("-F x("), Ok(0));
+    assert_eq!(greps.apply_search_patern("-i x(").unwrap_err().position, 5);
+
+    greps.new_grep("-C 1 -F Foo.run(").unwrap();
+    assert_eq!(lines(&greps, greps.selected), vec!["at Foo.run(Foo.java)", "at foo.run"]);
+    assert_eq!(greps.new_grep("-i x(").unwrap_err().position, 5);
+}
+
+#[test]
+fn search_wraps_around() {
+    let mut greps = Greps::new(vec![Chunk::from("a x\nb\nc x\nd\ne x")]);
+    assert_eq!(greps.next_search(), SearchStep::NoMatches);
+    assert_eq!(greps.apply_search_patern("x"), Ok(3));
+    assert_eq!(greps.search_position(), Some((1, 3)));
+
+    assert_eq!(greps.prev_search(), SearchStep::Wrapped);
+    assert_eq!(greps.current_grep().line_index, 4);
+    assert_eq!(greps.next_search(), SearchStep::Wrapped);
+    assert_eq!(greps.current_grep().line_index, 0);
+    assert_eq!(greps.next_search(), SearchStep::Moved);
+    assert_eq!(greps.search_position(), Some((2, 3)));
+
+    greps.change_current_line_index(3);
+    assert_eq!(greps.search_position(), Some((2, 3)));
+    assert_eq!(greps.next_search(), SearchStep::Moved);
+    assert_eq!(greps.current_grep().line_index, 4);
+
+    greps.wrap_search = false;
+    assert_eq!(greps.next_search(), SearchStep::Stopped);
+    assert_eq!(greps.current_grep().line_index, 4);
+}
+
+#[test]
+fn incremental_search() {
+    let mut greps = Greps::new(vec![Chunk::from("a\nb x\nc\nd\ne x\nf y")]);
+    greps.preview_search("x", 2, 2);
+    assert_eq!(greps.current_grep().line_index, 4);
+    assert_eq!(greps.decorations().len(), 1);
+
+    // A match on screen already doesn't move anything.
+    greps.preview_search("x", 3, 2);
+    assert_eq!(greps.current_grep().line_index, 3);
+    greps.preview_search("y(", 0, 2);
+    assert_eq!(greps.current_grep().line_index, 0);
+    assert!(greps.decorations().is_empty());
+    greps.preview_search("x", 5, 1);
+    assert_eq!(greps.current_grep().line_index, 1);
+
+    greps.end_preview();
+    assert!(greps.decorations().is_empty());
+    greps.change_current_line_index(2);
+    assert_eq!(greps.apply_search_patern("x"), Ok(2));
+    assert_eq!(greps.current_grep().line_index, 4);
+}
+
+#[test]
+fn close_grep_keeps_derived_ones() {
+    let mut greps = Greps::new(vec![Chunk::from("ERR db a\nINF b\nERR db c")]);
+    greps.new_grep("ERR").unwrap();
+    greps.new_grep("db").unwrap();
+    greps.select_root();
+    greps.new_grep("INF").unwrap();
+    greps.select_one_to_left();
+    greps.select_one_to_left();
+
+    greps.close_grep();
+    assert_eq!(greps.selected, 0);
+    let patterns = greps.greps.iter().map(|g| g.patern.as_str()).collect::<Vec<_>>();
+    assert_eq!(patterns, vec!["ROOT", "ROOT > ERR > db", "ROOT > INF"]);
+    assert_eq!(greps.greps[1].parent, None);
+    assert_eq!(greps.greps[2].parent, Some(0));
+
+    // The orphaned grep keeps its lines and doesn't pick new ones.
+    greps.append_lines(vec![Chunk::from("INF db x\nERR db y")]);
+    assert_eq!(lines(&greps, 1), vec!["ERR db a", "ERR db c"]);
+    assert_eq!(lines(&greps, 2), vec!["INF b", "INF db x"]);
+
+    greps.select_one_to_right();
+    greps.change_current_line_index(1);
+    greps.select_parent();
+    assert_eq!(greps.selected, 0);
+    assert_eq!(greps.current_grep().line_index, 2);
+    greps.select_one_to_right();
+    greps.close_grep();
+    assert_eq!(greps.greps.len(), 2);
+    assert_eq!(greps.selected, 0);
+    greps.close_grep();
+    assert_eq!(greps.greps.len(), 2);
+}
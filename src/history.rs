@@ -0,0 +1,212 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// Entries kept of each kind, older ones are dropped.
+const MAX_ENTRIES: usize = 500;
+
+/// Prompts with a history of their own.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HistoryKind {
+    Search,
+    Grep,
+    Command,
+}
+
+impl HistoryKind {
+    /// Marks entries of the kind in the history file, like the prompt does.
+    fn prefix(self) -> char {
+        match self {
+            HistoryKind::Search => '/',
+            HistoryKind::Grep => '&',
+            HistoryKind::Command => '#',
+        }
+    }
+
+    fn from_prefix(prefix: char) -> Option<HistoryKind> {
+        match prefix {
+            '/' => Some(HistoryKind::Search),
+            '&' => Some(HistoryKind::Grep),
+            '#' => Some(HistoryKind::Command),
+            _ => None,
+        }
+    }
+}
+
+/// What was typed at the prompts, oldest first, saved across runs.
+pub struct History {
+    path: Option<PathBuf>,
+    searches: Vec<String>,
+    greps: Vec<String>,
+    commands: Vec<String>,
+    /// Entries were added since the last save.
+    changed: bool,
+}
+
+impl History {
+    /// Reads the history file at `path`, a missing file is an empty history.
+    pub fn load(path: Option<PathBuf>) -> History {
+        let mut history = History {
+            path,
+            searches: Vec::new(),
+            greps: Vec::new(),
+            commands: Vec::new(),
+            changed: false,
+        };
+        let lines = history.path
+            .as_ref()
+            .and_then(|path| File::open(path).ok())
+            .map(|file| BufReader::new(file).lines().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for line in lines.into_iter().filter_map(Result::ok) {
+            let mut chars = line.chars();
+            if let Some(kind) = chars.next().and_then(HistoryKind::from_prefix) {
+                history.add(kind, chars.as_str());
+            }
+        }
+        history.changed = false;
+        history
+    }
+
+    pub fn entries(&self, kind: HistoryKind) -> &[String] {
+        match kind {
+            HistoryKind::Search => &self.searches,
+            HistoryKind::Grep => &self.greps,
+            HistoryKind::Command => &self.commands,
+        }
+    }
+
+    /// Makes `entry` the newest of its kind, dropping an older copy of it.
+    pub fn add(&mut self, kind: HistoryKind, entry: &str) {
+        if entry.trim().is_empty() {
+            return;
+        }
+        let entries = match kind {
+            HistoryKind::Search => &mut self.searches,
+            HistoryKind::Grep => &mut self.greps,
+            HistoryKind::Command => &mut self.commands,
+        };
+        entries.retain(|e| e != entry);
+        entries.push(entry.to_string());
+        if entries.len() > MAX_ENTRIES {
+            let excess = entries.len() - MAX_ENTRIES;
+            entries.drain(..excess);
+        }
+        self.changed = true;
+    }
+
+    /// Writes the history file when anything was added since it was read.
+    pub fn save(&mut self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) if self.changed => path,
+            _ => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = File::create(path)?;
+        for &kind in &[HistoryKind::Search, HistoryKind::Grep, HistoryKind::Command] {
+            for entry in self.entries(kind) {
+                writeln!(file, "{}{}", kind.prefix(), entry)?;
+            }
+        }
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// Walks through entries from the newest with Up and Down, the line typed
+/// before is kept to get back to.
+#[derive(Default)]
+pub struct Recall {
+    index: Option<usize>,
+    draft: String,
+}
+
+impl Recall {
+    pub fn older(&mut self, entries: &[String], typed: &str) -> Option<String> {
+        let index = match self.index {
+            None => entries.len().checked_sub(1)?,
+            Some(index) => index.checked_sub(1)?,
+        };
+        if self.index.is_none() {
+            self.draft = typed.to_string();
+        }
+        self.index = Some(index);
+        entries.get(index).cloned()
+    }
+
+    pub fn newer(&mut self, entries: &[String]) -> Option<String> {
+        let index = self.index?;
+        if index + 1 < entries.len() {
+            self.index = Some(index + 1);
+            return entries.get(index + 1).cloned();
+        }
+        self.index = None;
+        Some(self.draft.clone())
+    }
+}
+
+/// Newest entry containing `query`, among the ones before `before`.
+pub fn find_back(entries: &[String], query: &str, before: usize) -> Option<usize> {
+    entries[..before.min(entries.len())].iter().rposition(|entry| entry.contains(query))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(data_home.join("rustgrepper").join("history"))
+}
+
+#[test]
+fn history_entries() {
+    let mut history = History::load(None);
+    history.add(HistoryKind::Grep, "ERR");
+    history.add(HistoryKind::Grep, "timeout");
+    history.add(HistoryKind::Grep, "ERR");
+    history.add(HistoryKind::Search, " ");
+    assert_eq!(history.entries(HistoryKind::Grep), &["timeout", "ERR"]);
+    assert!(history.entries(HistoryKind::Search).is_empty());
+
+    for idx in 0..MAX_ENTRIES {
+        history.add(HistoryKind::Command, &format!("goto {}", idx));
+    }
+    history.add(HistoryKind::Command, "help");
+    assert_eq!(history.entries(HistoryKind::Command).len(), MAX_ENTRIES);
+    assert_eq!(history.entries(HistoryKind::Command)[0], "goto 1");
+}
+
+#[test]
+fn save_and_load_history() {
+    let dir = ::utils::TempDir::new("history");
+    let path = dir.join("history");
+    let mut history = History::load(Some(path.clone()));
+    history.add(HistoryKind::Search, "#1 and /x");
+    history.add(HistoryKind::Grep, "-C 2 ERR");
+    history.add(HistoryKind::Command, "next");
+    history.save().unwrap();
+
+    let history = History::load(Some(path.clone()));
+    assert_eq!(history.entries(HistoryKind::Search), &["#1 and /x"]);
+    assert_eq!(history.entries(HistoryKind::Grep), &["-C 2 ERR"]);
+    assert_eq!(history.entries(HistoryKind::Command), &["next"]);
+}
+
+#[test]
+fn recall_entries() {
+    let entries = vec!["a".to_string(), "b".to_string()];
+    let mut recall = Recall::default();
+    assert_eq!(recall.newer(&entries), None);
+    assert_eq!(recall.older(&entries, "typed"), Some("b".to_string()));
+    assert_eq!(recall.older(&entries, "b"), Some("a".to_string()));
+    assert_eq!(recall.older(&entries, "a"), None);
+    assert_eq!(recall.newer(&entries), Some("b".to_string()));
+    assert_eq!(recall.newer(&entries), Some("typed".to_string()));
+    assert_eq!(recall.newer(&entries), None);
+
+    assert_eq!(find_back(&entries, "a", 2), Some(0));
+    assert_eq!(find_back(&entries, "b", 1), None);
+}
@@ -0,0 +1,282 @@
+use std::borrow::Cow;
+use std::mem;
+use std::ops::Range;
+use std::str;
+
+use encoding::Encoding;
+
+/// Lines sent at once by a producer thread, consecutive lines of one
+/// source with their text back to back.
+pub struct Chunk {
+    /// Text of the lines with their line endings, as the source has it.
+    pub text: &'static [u8],
+    /// Byte offset of each line in its source, the first one is where
+    /// `text` begins.
+    pub starts: Vec<u64>,
+    /// Number of the first line in its source, counted from 1.
+    pub number: usize,
+    /// Index of the source when several are read as one.
+    pub source: usize,
+    /// Text of the lines that had to be decoded, by their index in the chunk.
+    pub decoded: Vec<(usize, &'static str)>,
+}
+
+impl Chunk {
+    /// Lines of `text`, as if read in UTF-8.
+    #[allow(unused)]
+    pub fn from(text: &str) -> Chunk {
+        let mut builder = ChunkBuilder::new(Encoding::Utf8);
+        for line in text.split_inclusive('\n') {
+            builder.push(line.as_bytes());
+        }
+        builder.take()
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    pub fn line(&self, index: usize) -> &'static str {
+        let begin = (self.starts[index] - self.starts[0]) as usize;
+        let end = self.starts
+            .get(index + 1)
+            .map_or(self.text.len(), |&next| (next - self.starts[0]) as usize);
+        text(&self.text[begin..end], &self.decoded, index)
+    }
+
+    /// The lines in `range`, without copying their text.
+    pub fn lines(&self, range: Range<usize>) -> Chunk {
+        let begin = (self.starts[range.start] - self.starts[0]) as usize;
+        let end = self.starts
+            .get(range.end)
+            .map_or(self.text.len(), |&next| (next - self.starts[0]) as usize);
+        Chunk {
+            text: &self.text[begin..end],
+            starts: self.starts[range.clone()].to_vec(),
+            number: self.number + range.start,
+            source: self.source,
+            decoded: self.decoded
+                .iter()
+                .filter(|&&(index, _)| range.contains(&index))
+                .map(|&(index, text)| (index - range.start, text))
+                .collect(),
+        }
+    }
+}
+
+/// Collects lines read one at a time into chunks.
+pub struct ChunkBuilder {
+    text: Vec<u8>,
+    starts: Vec<u64>,
+    decoded: Vec<(usize, &'static str)>,
+    encoding: Encoding,
+    // Number and offset of the next line.
+    number: usize,
+    offset: u64,
+}
+
+impl ChunkBuilder {
+    pub fn new(encoding: Encoding) -> ChunkBuilder {
+        ChunkBuilder {
+            text: Vec::new(),
+            starts: Vec::new(),
+            decoded: Vec::new(),
+            encoding,
+            number: 1,
+            offset: 0,
+        }
+    }
+
+    /// Adds a line with its line ending, only the last line of a source may
+    /// have none.
+    pub fn push(&mut self, line: &[u8]) {
+        if let Some(text) = decode(line, self.encoding) {
+            self.decoded.push((self.starts.len(), text));
+        }
+        self.starts.push(self.offset);
+        self.text.extend_from_slice(line);
+        self.offset += line.len() as u64;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// The lines added since the last call, their text is leaked.
+    pub fn take(&mut self) -> Chunk {
+        let text = mem::take(&mut self.text).into_boxed_slice();
+        let chunk = Chunk {
+            text: Box::leak(text),
+            starts: mem::take(&mut self.starts),
+            number: self.number,
+            source: 0,
+            decoded: mem::take(&mut self.decoded),
+        };
+        self.number += chunk.len();
+        chunk
+    }
+
+    /// The source starts over from its beginning, like a truncated file.
+    pub fn restart(&mut self) {
+        self.number = 1;
+        self.offset = 0;
+    }
+}
+
+/// Text of a line that can't be shown as it is, like invalid UTF-8 or
+/// control characters, leaked. `None` for the others.
+pub fn decode(line: &[u8], encoding: Encoding) -> Option<&'static str> {
+    match encoding.decode_line(strip_line_ending(line)) {
+        Cow::Borrowed(_) => None,
+        Cow::Owned(text) => Some(Box::leak(text.into_boxed_str())),
+    }
+}
+
+fn strip_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Text of line `index` of `decoded`, whose bytes are `line`.
+fn text(line: &'static [u8], decoded: &[(usize, &'static str)], index: usize) -> &'static str {
+    match decoded.binary_search_by_key(&index, |&(index, _)| index) {
+        Ok(found) => decoded[found].1,
+        // Checked by `decode`, unless the file changed since.
+        Err(_) => str::from_utf8(strip_line_ending(line)).unwrap_or(""),
+    }
+}
+
+/// Where a line is in its source.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Position {
+    /// Index of the source when several are read as one, like merged files.
+    pub source: usize,
+    /// Number of the line, counted from 1.
+    pub number: usize,
+    /// Byte offset of the line, after decompression.
+    pub offset: u64,
+}
+
+/// Lines of a chunk, as `LineIndex` keeps them.
+struct Run {
+    /// Index of the first line.
+    first: usize,
+    text: &'static [u8],
+    number: usize,
+    source: usize,
+}
+
+/// All lines of a stream, looked up by their index.
+///
+/// The text stays where the producer put it, like in a memory mapped file,
+/// a line costs the 8 bytes of its offset, plus its text when it had to be
+/// decoded. Chunks add a few bytes each.
+pub struct LineIndex {
+    starts: Vec<u64>,
+    runs: Vec<Run>,
+    decoded: Vec<(usize, &'static str)>,
+}
+
+impl LineIndex {
+    pub fn new() -> LineIndex {
+        LineIndex {
+            starts: Vec::new(),
+            runs: Vec::new(),
+            decoded: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn append(&mut self, chunk: Chunk) {
+        if chunk.is_empty() {
+            return;
+        }
+        let first = self.starts.len();
+        self.decoded.extend(chunk.decoded.iter().map(|&(index, text)| (first + index, text)));
+        self.starts.extend_from_slice(&chunk.starts);
+        self.runs.push(Run {
+            first,
+            text: chunk.text,
+            number: chunk.number,
+            source: chunk.source,
+        });
+    }
+
+    fn run_index(&self, index: usize) -> usize {
+        match self.runs.binary_search_by_key(&index, |run| run.first) {
+            Ok(run) => run,
+            Err(run) => run - 1,
+        }
+    }
+
+    pub fn text(&self, index: usize) -> &'static str {
+        let run_index = self.run_index(index);
+        let run = &self.runs[run_index];
+        let end = self.runs.get(run_index + 1).map_or(self.starts.len(), |next| next.first);
+        let first = self.starts[run.first];
+        let begin = (self.starts[index] - first) as usize;
+        let end = if index + 1 < end {
+            (self.starts[index + 1] - first) as usize
+        } else {
+            run.text.len()
+        };
+        text(&run.text[begin..end], &self.decoded, index)
+    }
+
+    pub fn position(&self, index: usize) -> Position {
+        let run = &self.runs[self.run_index(index)];
+        Position {
+            source: run.source,
+            number: run.number + index - run.first,
+            offset: self.starts[index],
+        }
+    }
+}
+
+#[test]
+fn chunk_lines() {
+    let mut builder = ChunkBuilder::new(Encoding::Utf8);
+    for line in &[&b"first\r\n"[..], b"bad \xff\n", b"\n", b"last"] {
+        builder.push(line);
+    }
+    let chunk = builder.take();
+
+    let lines = (0..chunk.len()).map(|idx| chunk.line(idx)).collect::<Vec<_>>();
+    assert_eq!(lines, vec!["first", "bad \\xFF", "", "last"]);
+    let tail = chunk.lines(1..4);
+    assert_eq!((tail.number, tail.starts.clone()), (2, vec![7, 13, 14]));
+    assert_eq!((tail.line(0), tail.line(2)), ("bad \\xFF", "last"));
+
+    builder.push(b"next\n");
+    assert_eq!(builder.take().number, 5);
+}
+
+#[test]
+fn line_index_lookup() {
+    let mut index = LineIndex::new();
+    index.append(Chunk::from("a\nbb\n"));
+    let mut chunk = Chunk::from("x\ny \x01\n");
+    chunk.source = 1;
+    chunk.number = 7;
+    index.append(chunk);
+    index.append(Chunk::from(""));
+    index.append(Chunk::from("c"));
+
+    let lines = (0..index.len())
+        .map(|idx| (index.text(idx), index.position(idx)))
+        .map(|(text, p)| (text, p.source, p.number, p.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(lines,
+               vec![("a", 0, 1, 0),
+                    ("bb", 0, 2, 2),
+                    ("x", 1, 7, 0),
+                    ("y \\x01", 1, 8, 2),
+                    ("c", 0, 1, 0)]);
+}
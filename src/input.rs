@@ -0,0 +1,535 @@
+use std::io::prelude::*;
+use std::io::{self, BufReader, SeekFrom};
+use std::fs::File;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::mem;
+use std::process;
+use std::ptr;
+use std::sync::{Arc, Once};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use libc;
+use memmap2::Mmap;
+use regex::{self, Regex};
+use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use encoding::{self, Encoding, Utf16Reader};
+use index::{self, ChunkBuilder};
+
+pub use index::Chunk;
+
+/// Upper bound of lines moved into the pager on a single poll, so a fast
+/// producer can't starve key handling.
+const MAX_LINES_PER_POLL: usize = 100_000;
+
+/// Number of lines indexed from a memory mapped file before they are handed
+/// over to the pager.
+const MMAP_INDEX_CHUNK: usize = 65_536;
+
+/// How often a followed file is checked for appended data.
+const FOLLOW_INTERVAL_MS: u64 = 250;
+
+/// Memory mapped files guarded against truncation at once, more are read
+/// like streams.
+const MAX_GUARDED_MAPPINGS: usize = 64;
+
+// Address and length of each guarded mapping, 0 for free slots. Looked up
+// by `on_sigbus`, which can only use atomics.
+static MAPPING_STARTS: [AtomicUsize; MAX_GUARDED_MAPPINGS] =
+    [const { AtomicUsize::new(0) }; MAX_GUARDED_MAPPINGS];
+static MAPPING_LENGTHS: [AtomicUsize; MAX_GUARDED_MAPPINGS] =
+    [const { AtomicUsize::new(0) }; MAX_GUARDED_MAPPINGS];
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Text read line by line on a background thread.
+///
+/// The text of lines is `'static`: it stays alive as long as the pager does,
+/// so the pager can keep borrowing it while more text arrives.
+pub struct LineStream {
+    receiver: Receiver<Chunk>,
+    binary: Arc<AtomicBool>,
+}
+
+impl LineStream {
+    /// Runs `producer` on its own thread, it sends lines until it returns.
+    #[allow(unused)]
+    pub fn spawn<F>(producer: F) -> LineStream
+        where F: FnOnce(&Sender<Chunk>) + Send + 'static
+    {
+        LineStream::spawn_checked(move |sender, _| producer(sender))
+    }
+
+    /// Like `spawn`, the producer also flags data that doesn't look like
+    /// text, before sending any of it.
+    pub fn spawn_checked<F>(producer: F) -> LineStream
+        where F: FnOnce(&Sender<Chunk>, &AtomicBool) + Send + 'static
+    {
+        let (sender, receiver) = channel();
+        let binary = Arc::new(AtomicBool::new(false));
+        let flag = binary.clone();
+        thread::spawn(move || producer(&sender, &flag));
+        LineStream { receiver, binary }
+    }
+
+    /// Reads lines of `reader`, decompressing it when it starts with the
+    /// magic bytes of a known format.
+    pub fn from_reader<R>(reader: R, encoding: Encoding) -> LineStream
+        where R: Read + Send + 'static
+    {
+        LineStream::spawn_checked(move |sender, binary| if let Ok(reader) = decompressed(reader) {
+                                      read_decoded(BufReader::new(reader),
+                                                   encoding,
+                                                   sender,
+                                                   binary);
+                                  })
+    }
+
+    /// Memory maps the file and indexes its lines in the background, lines
+    /// borrow straight from the mapping.
+    ///
+    /// A file truncated while it's mapped, like by `logrotate` with
+    /// `copytruncate`, reads as zeros past its new end, instead of killing
+    /// the pager with SIGBUS.
+    pub fn from_file(path: &Path, encoding: Encoding) -> io::Result<LineStream> {
+        let mut file = File::open(path)?;
+        let header = read_header(&mut file)?;
+        let encoding = encoding.detect(&header);
+        if header.is_empty() || Compression::detect(&header).is_some() ||
+           !encoding.is_byte_oriented() {
+            return Ok(LineStream::from_reader(file, encoding));
+        }
+        let mapping = unsafe { Mmap::map(&file)? };
+        if !guard_mapping(&mapping) {
+            return Ok(LineStream::from_reader(file, encoding));
+        }
+        let mapping: &'static Mmap = Box::leak(Box::new(mapping));
+        Ok(LineStream::spawn_checked(move |sender, binary| {
+            binary.store(encoding::looks_binary(mapping), Ordering::Relaxed);
+            index_lines(mapping, encoding, sender)
+        }))
+    }
+
+    /// Reads the file like `from_file`, then keeps waiting for data appended
+    /// to it, like `tail -f`. UTF-16 files are read once.
+    pub fn follow_file(path: &Path, encoding: Encoding) -> io::Result<LineStream> {
+        let mut file = File::open(path)?;
+        let header = read_header(&mut file)?;
+        let encoding = encoding.detect(&header);
+        if Compression::detect(&header).is_some() || !encoding.is_byte_oriented() {
+            return Ok(LineStream::from_reader(file, encoding));
+        }
+        Ok(LineStream::spawn_checked(move |sender, binary| {
+            binary.store(encoding::looks_binary(&header), Ordering::Relaxed);
+            follow(BufReader::new(file), encoding, sender)
+        }))
+    }
+
+    pub fn from_stdin(encoding: Encoding) -> LineStream {
+        LineStream::from_reader(io::stdin(), encoding)
+    }
+
+    /// Whether the data read so far looks like a binary file rather than
+    /// text.
+    pub fn is_binary(&self) -> bool {
+        self.binary.load(Ordering::Relaxed)
+    }
+
+    /// Chunks of lines read so far, without blocking.
+    pub fn poll(&mut self) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        let mut lines = 0;
+        while lines < MAX_LINES_PER_POLL {
+            match self.receiver.try_recv() {
+                Ok(chunk) => {
+                    lines += chunk.len();
+                    chunks.push(chunk);
+                }
+                Err(_) => break,
+            }
+        }
+        chunks
+    }
+
+    /// Waits for the next chunk of lines, for threads consuming the stream.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Chunk, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+
+    /// Waits for the next chunk of lines, `None` once the stream has ended.
+    pub fn recv(&self) -> Option<Chunk> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Reads the streams one after another, chunks carry the index of their
+/// stream in `Chunk::source`.
+pub fn concat(streams: Vec<LineStream>) -> LineStream {
+    LineStream::spawn_checked(move |sender, binary| for (index, stream) in streams.into_iter()
+        .enumerate() {
+        while let Some(mut chunk) = stream.recv() {
+            if stream.is_binary() {
+                binary.store(true, Ordering::Relaxed);
+            }
+            chunk.source = index;
+            if sender.send(chunk).is_err() {
+                return;
+            }
+        }
+    })
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn detect(header: &[u8]) -> Option<Compression> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if header.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Streams that were concatenated, like appended gzip members, are all
+    /// decoded.
+    fn decoder<R>(self, reader: R) -> io::Result<Box<dyn Read + Send>>
+        where R: BufRead + Send + 'static
+    {
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+            Compression::Zstd => Box::new(ZstdDecoder::with_buffer(reader)?),
+        })
+    }
+}
+
+/// Reads the beginning of the file, for magic bytes and binary data, and
+/// rewinds it.
+fn read_header(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(encoding::BINARY_CHECK_LEN);
+    Read::by_ref(file).take(encoding::BINARY_CHECK_LEN as u64).read_to_end(&mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(header)
+}
+
+/// Registers the mapping with the SIGBUS handler, false when too many are
+/// registered already. Mappings are never unmapped, so they stay registered.
+fn guard_mapping(mapping: &[u8]) -> bool {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        PAGE_SIZE.store(libc::sysconf(libc::_SC_PAGESIZE) as usize, Ordering::SeqCst);
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = on_sigbus as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGBUS, &action, ptr::null_mut());
+    });
+    let start = mapping.as_ptr() as usize;
+    for (slot, length) in MAPPING_STARTS.iter().zip(&MAPPING_LENGTHS) {
+        if slot.compare_exchange(0, start, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            length.store(mapping.len(), Ordering::SeqCst);
+            return true;
+        }
+    }
+    false
+}
+
+/// Pages of a guarded mapping past the end of its file are replaced with
+/// zeroed ones, and the read that faulted is done again. Other faults get
+/// the default action.
+extern "C" fn on_sigbus(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let address = unsafe { (*info).si_addr() } as usize;
+    let page_size = PAGE_SIZE.load(Ordering::SeqCst);
+    for (slot, length) in MAPPING_STARTS.iter().zip(&MAPPING_LENGTHS) {
+        let start = slot.load(Ordering::SeqCst);
+        if start == 0 || address < start || address >= start + length.load(Ordering::SeqCst) {
+            continue;
+        }
+        let page = (address & !(page_size - 1)) as *mut libc::c_void;
+        let zeroed = unsafe {
+            libc::mmap(page,
+                       page_size,
+                       libc::PROT_READ,
+                       libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                       -1,
+                       0)
+        };
+        if zeroed != libc::MAP_FAILED {
+            return;
+        }
+    }
+    unsafe {
+        libc::signal(libc::SIGBUS, libc::SIG_DFL);
+    }
+}
+
+/// Peeks at the beginning of `reader` for magic bytes, the returned reader
+/// still yields all of its data.
+fn decompressed<R>(reader: R) -> io::Result<Box<dyn Read + Send>>
+    where R: Read + Send + 'static
+{
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::detect(reader.fill_buf()?);
+    match compression {
+        Some(compression) => compression.decoder(reader),
+        None => Ok(Box::new(reader)),
+    }
+}
+
+/// Splits the mapping into chunks of lines, which borrow its text. Only
+/// lines that had to be decoded take memory of their own.
+fn index_lines(mapping: &'static [u8], encoding: Encoding, sender: &Sender<Chunk>) {
+    let mut number = 1;
+    let mut begin = 0;
+    while begin < mapping.len() {
+        let first = begin;
+        let mut starts = Vec::with_capacity(MMAP_INDEX_CHUNK);
+        let mut decoded = Vec::new();
+        while begin < mapping.len() && starts.len() < MMAP_INDEX_CHUNK {
+            let end = mapping[begin..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(mapping.len(), |idx| begin + idx + 1);
+            if let Some(text) = index::decode(&mapping[begin..end], encoding) {
+                decoded.push((starts.len(), text));
+            }
+            starts.push(begin as u64);
+            begin = end;
+        }
+        let chunk = Chunk {
+            text: &mapping[first..begin],
+            starts,
+            number,
+            source: 0,
+            decoded,
+        };
+        number += chunk.len();
+        if sender.send(chunk).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends lines whenever reading further could block, so slow producers show
+/// up line by line while fast ones are batched. An unterminated last line is
+/// left in `line`. Returns false once there is nothing more to read for the
+/// pager.
+fn read_lines<R: Read>(reader: &mut BufReader<R>,
+                       line: &mut Vec<u8>,
+                       lines: &mut ChunkBuilder,
+                       sender: &Sender<Chunk>)
+                       -> bool {
+    loop {
+        match reader.read_until(b'\n', line) {
+            Ok(0) => break,
+            Ok(_) => {
+                if !line.ends_with(b"\n") {
+                    continue;
+                }
+                lines.push(line);
+                line.clear();
+                if reader.buffer().is_empty() && sender.send(lines.take()).is_err() {
+                    return false;
+                }
+            }
+            Err(_) => {
+                let _ = sender.send(lines.take());
+                return false;
+            }
+        }
+    }
+    lines.is_empty() || sender.send(lines.take()).is_ok()
+}
+
+fn read_all<R: Read>(mut reader: BufReader<R>, encoding: Encoding, sender: &Sender<Chunk>) {
+    let mut line = Vec::new();
+    let mut lines = ChunkBuilder::new(encoding);
+    if read_lines(&mut reader, &mut line, &mut lines, sender) && !line.is_empty() {
+        lines.push(&line);
+        let _ = sender.send(lines.take());
+    }
+}
+
+/// Picks the encoding from a byte order mark, UTF-16 is transcoded before
+/// it's split into lines.
+fn read_decoded<R: Read>(mut reader: BufReader<R>,
+                         encoding: Encoding,
+                         sender: &Sender<Chunk>,
+                         binary: &AtomicBool) {
+    let encoding = match reader.fill_buf() {
+        Ok(header) => encoding.detect(header),
+        Err(_) => return,
+    };
+    if encoding.is_byte_oriented() {
+        if let Ok(header) = reader.fill_buf() {
+            binary.store(encoding::looks_binary(header), Ordering::Relaxed);
+        }
+        read_all(reader, encoding, sender);
+    } else {
+        read_all(BufReader::new(Utf16Reader::new(reader, encoding)), Encoding::Utf8, sender);
+    }
+}
+
+fn follow(mut reader: BufReader<File>, encoding: Encoding, sender: &Sender<Chunk>) {
+    // An unterminated line is still being written, it waits for the rest.
+    let mut line = Vec::new();
+    let mut lines = ChunkBuilder::new(encoding);
+    while read_lines(&mut reader, &mut line, &mut lines, sender) {
+        // A file shorter than what was already read has been truncated by
+        // log rotation, start over from its beginning.
+        let offset = reader.stream_position().unwrap_or(0);
+        let length = reader.get_ref().metadata().map(|m| m.len()).unwrap_or(offset);
+        if length < offset {
+            line.clear();
+            lines.restart();
+            if reader.seek(SeekFrom::Start(0)).is_err() {
+                return;
+            }
+        }
+        thread::sleep(Duration::from_millis(FOLLOW_INTERVAL_MS));
+    }
+}
+
+pub fn stdin_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+/// Opens the file at `path`, `-` stands for stdin. Exits on errors.
+pub fn open(path: &str, follow: bool, encoding: Encoding) -> LineStream {
+    match try_open(path, follow, encoding) {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Like `open`, errors are returned instead.
+pub fn try_open(path: &str, follow: bool, encoding: Encoding) -> io::Result<LineStream> {
+    if path == "-" {
+        return Ok(LineStream::from_stdin(encoding));
+    }
+    let path = Path::new(path);
+    if follow {
+        LineStream::follow_file(path, encoding)
+    } else {
+        LineStream::from_file(path, encoding)
+    }
+}
+
+/// Rotated siblings of `path`, oldest first, followed by `path` itself.
+///
+/// Both numbered (`app.log.1`, `app.log.2.gz`) and dated (`app.log-20170502`)
+/// rotations are picked up, compressed or not.
+pub fn rotated_family(path: &Path) -> Vec<PathBuf> {
+    let mut family = Vec::new();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = path.file_name().and_then(|name| name.to_str());
+    if let (Some(name), Ok(entries)) = (name, fs::read_dir(&dir)) {
+        let pattern = format!(r"^{}(?:\.(\d+)|-(\d{{8,10}}))(?:\.(?:gz|bz2|xz|zst))?$",
+                              regex::escape(name));
+        let re = Regex::new(&pattern).unwrap();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let file_name = entry.file_name();
+            let caps = match file_name.to_str().and_then(|n| re.captures(n)) {
+                Some(caps) => caps,
+                None => continue,
+            };
+            // Dated rotations first, in order, then numbered ones from the
+            // highest number down.
+            let key = match (caps.get(1), caps.get(2)) {
+                (Some(number), _) => (1, u64::MAX - number.as_str().parse().unwrap_or(0)),
+                (_, Some(date)) => (0, date.as_str().parse().unwrap_or(0)),
+                _ => continue,
+            };
+            family.push((key, dir.join(&file_name)));
+        }
+    }
+    family.sort();
+    let mut family = family.into_iter().map(|(_, path)| path).collect::<Vec<_>>();
+    family.push(path.to_path_buf());
+    family
+}
+
+#[test]
+fn index_lines_splits_mapping() {
+    let (sender, receiver) = channel();
+    index_lines(b"first\r\nsecond\n\nlast \xff", Encoding::Utf8, &sender);
+
+    let chunk = receiver.recv().unwrap();
+    assert_eq!((0..chunk.len()).map(|idx| chunk.line(idx)).collect::<Vec<_>>(),
+               vec!["first", "second", "", "last \\xFF"]);
+    assert_eq!((chunk.number, chunk.starts), (1, vec![0, 7, 14, 15]));
+}
+
+#[test]
+fn read_truncated_mapping() {
+    let dir = ::utils::TempDir::new("truncated");
+    let path = dir.join("app.log");
+    fs::write(&path, "line of a log that gets truncated\n".repeat(1000)).unwrap();
+    let stream = LineStream::from_file(&path, Encoding::Utf8).unwrap();
+    let chunk = stream.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    File::create(&path).unwrap();
+    assert!(chunk.line(999).bytes().all(|b| b == 0));
+}
+
+#[test]
+fn detect_compression() {
+    assert_eq!(Compression::detect(&[0x1f, 0x8b, 0x08]), Some(Compression::Gzip));
+    assert_eq!(Compression::detect(b"BZh91AY"), Some(Compression::Bzip2));
+    assert_eq!(Compression::detect(b"\xfd7zXZ\x00\x00"), Some(Compression::Xz));
+    assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Some(Compression::Zstd));
+    assert_eq!(Compression::detect(b"2017-05-02 INF"), None);
+    assert_eq!(Compression::detect(b""), None);
+}
+
+#[test]
+fn read_gzip_stream() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"first\nsecond\n").unwrap();
+    let stream = LineStream::from_reader(io::Cursor::new(encoder.finish().unwrap()),
+                                         Encoding::Utf8);
+
+    let chunk = stream.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!((chunk.line(0), chunk.line(1)), ("first", "second"));
+    assert_eq!((chunk.number, chunk.starts), (1, vec![0, 6]));
+}
+
+#[test]
+fn rotated_family_order() {
+    let dir = ::utils::TempDir::new("rotated");
+    for name in &["app.log", "app.log.1", "app.log.2.gz", "app.log.10.xz", "app.log-20170501",
+                  "app.log.bak", "other.log.1"] {
+        File::create(dir.join(name)).unwrap();
+    }
+    let family = rotated_family(&dir.join("app.log"));
+
+    let names = family.iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names,
+               vec!["app.log-20170501", "app.log.10.xz", "app.log.2.gz", "app.log.1", "app.log"]);
+}
@@ -1,50 +1,231 @@
+// The original code and its tests are kept in their own style.
+#![allow(clippy::needless_borrow, clippy::needless_return, clippy::useless_format,
+         clippy::wrong_self_convention)]
+
+extern crate bzip2;
+extern crate flate2;
 extern crate ncurses;
 extern crate regex;
+extern crate regex_syntax;
+extern crate unicode_width;
+extern crate libc;
+extern crate memmap2;
+extern crate xz2;
+extern crate zstd;
 
 mod utils;
 mod prompt;
 mod pager;
 mod curses_pager;
 mod greps;
+mod input;
+mod options;
+mod files;
+mod merge;
+mod encoding;
+mod expression;
+mod editor;
+mod history;
+mod commands;
+mod vocabulary;
+mod index;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
+use commands::Setting;
 use prompt::*;
 use curses_pager::*;
 use pager::*;
 use greps::*;
+use files::*;
+use vocabulary::Vocabulary;
+
+fn bottom_line_index(greps: &Greps, printed_lines: usize) -> usize {
+    (greps.lines_count() + 1).saturating_sub(printed_lines)
+}
+
+/// Tells where stepping through search matches went, when it's not just the
+/// next one.
+fn report_search(pager: &mut CursesPager, step: SearchStep, forward: bool) {
+    match step {
+        SearchStep::Moved => {}
+        SearchStep::Wrapped if forward => {
+            pager.info("Search hit BOTTOM, continuing at TOP".to_string())
+        }
+        SearchStep::Wrapped => pager.info("Search hit TOP, continuing at BOTTOM".to_string()),
+        SearchStep::Stopped if forward => pager.warn("No more matches".to_string()),
+        SearchStep::Stopped => pager.warn("No earlier matches".to_string()),
+        SearchStep::NoMatches => pager.warn("No search matches".to_string()),
+    }
+}
+
+/// The greps of the current file as the prompt sees them.
+struct View<'a> {
+    greps: &'a mut Greps,
+    label: &'a str,
+    sources: &'a [String],
+    /// Line on top when the prompt opened.
+    index: usize,
+    printed_lines: usize,
+    /// Collected on the first completion.
+    vocabulary: Option<Vocabulary>,
+}
+
+impl<'a> PromptContext<CursesPager> for View<'a> {
+    /// The search being typed is shown from the line on top, it goes back
+    /// there when the search is cancelled.
+    fn preview(&mut self, pager: &mut CursesPager, typed: Option<&str>) {
+        match typed {
+            Some(pattern) => self.greps.preview_search(pattern, self.index, self.printed_lines),
+            None => {
+                self.greps.end_preview();
+                self.greps.change_current_line_index(self.index);
+            }
+        }
+        self.redraw(pager);
+    }
+
+    fn redraw(&mut self, pager: &mut CursesPager) {
+        let index = self.greps.current_grep().line_index;
+        pager.mv_cursor((0, 0));
+        pager.print_logs(self.greps, index, self.sources);
+        pager.status(self.label, self.greps);
+    }
+
+    fn words(&mut self, prefix: &str) -> Vec<String> {
+        let greps = &self.greps;
+        self.vocabulary
+            .get_or_insert_with(|| {
+                let index = greps.current_grep().line_index;
+                let around = vocabulary::around(greps.lines_count(), index);
+                Vocabulary::from_lines(around.map(|idx| greps.text(idx)))
+            })
+            .complete(prefix)
+    }
+}
+
+/// Writes the lines of the current grep to the file at `path`.
+fn save_lines(greps: &Greps, path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for idx in 0..greps.lines_count() {
+        writeln!(file, "{}", greps.text(idx))?;
+    }
+    file.flush()
+}
 
 fn main() {
-    let buffer = utils::test_buffer_from_file();
+    let options = options::from_env();
+    let mut files = LogFiles::open(&options);
 
     let mut pager = CursesPager::new();
+    pager.wrap = options.wrap;
+    pager.line_numbers = options.line_numbers;
+    let mut history = history::History::load(history::default_path());
     pager.initialize();
-    let mut greps = Greps::new(utils::Text::from(&buffer).lines);
-    greps.decorations.insert(String::from("errors"),
-                             utils::DecorationPattern::from_single_attr(utils::Attribute::Red,
-                                                                        "ERR|error"));
-    greps.decorations.insert(String::from("informations"),
-                             utils::DecorationPattern::from_single_attr(utils::Attribute::Blue,
-                                                                        "INF|INFO|inf|info"));
+    let mut printed_lines = 0;
+    let mut redraw = true;
     loop {
+        for (idx, file) in files.files.iter_mut().enumerate() {
+            if idx != files.selected {
+                file.poll();
+                continue;
+            }
+            let index = file.greps.current_grep().line_index;
+            let lines_count = file.greps.lines_count();
+            let at_bottom = printed_lines > 0 && index + printed_lines + 1 >= lines_count;
+            if file.poll() {
+                let greps = &mut file.greps;
+                if at_bottom && greps.current_grep().line_index == index {
+                    let last_index = greps.lines_count().saturating_sub(pager.logs_height());
+                    greps.change_current_line_index(last_index.max(index));
+                }
+                redraw = true;
+            }
+        }
+
+        let label = files.label();
+        let sources = files.current_file().sources.clone();
+        let greps = &mut files.current_file_mut().greps;
         let index = greps.current_grep().line_index;
-        let printed_lines = pager.print_logs(&greps.current_grep().lines[index..],
-                                             greps.decorations());
+        if redraw {
+            pager.mv_cursor((0, 0));
+            printed_lines = pager.print_logs(greps, index, &sources);
+            pager.status(&label, greps);
+        } else {
+            let prompt_line = pager.term_size().1 - 1;
+            pager.mv_cursor((0, prompt_line));
+        }
+        redraw = true;
 
-        pager.status(&greps);
-        match prompt(&mut pager, PromptMode::Visual) {
+        let action = {
+            let mut view = View {
+                greps,
+                label: &label,
+                sources: &sources,
+                index,
+                printed_lines,
+                vocabulary: None,
+            };
+            prompt(&mut pager, PromptMode::Visual, &mut history, &mut view)
+        };
+        greps.end_preview();
+        if let Err(err) = history.save() {
+            pager.warn(format!("Couldn't save the history: {}", err));
+        }
+        if !matches!(action, Prompt::Refresh) {
+            pager.clear_message();
+        }
+        match action {
             Prompt::Exit => break,
             Prompt::SearchPattern(pat) => {
-                greps.apply_search_patern(&pat);
+                match greps.apply_search_patern(&pat) {
+                    Ok(0) => pager.warn(format!("Pattern not found: {}", pat)),
+                    Ok(1) => pager.info("1 match".to_string()),
+                    Ok(matches) => pager.info(format!("{} matches", matches)),
+                    Err(err) => pager.error(format!("search {}: {}", pat, err)),
+                }
             }
             Prompt::GrepPattern(pat) => {
-                greps.new_grep(&pat);
-                pager.clear();
+                match greps.new_grep(&pat) {
+                    Ok(()) => pager.clear(),
+                    Err(err) => pager.error(format!("grep {}: {}", pat, err)),
+                }
             }
             Prompt::GrepLeft => greps.select_one_to_left(),
             Prompt::GrepRight => greps.select_one_to_right(),
+            Prompt::ParentGrep => {
+                greps.select_parent();
+                pager.clear();
+            }
+            Prompt::RootGrep => {
+                greps.select_root();
+                pager.clear();
+            }
+            Prompt::ScrollLeft if pager.wrap => greps.select_one_to_left(),
+            Prompt::ScrollRight if pager.wrap => greps.select_one_to_right(),
+            Prompt::ScrollLeft => pager.scroll_left(),
+            Prompt::ScrollRight => pager.scroll_right(),
+            Prompt::ToggleWrap => {
+                pager.toggle_wrap();
+                pager.clear();
+            }
+            Prompt::ToggleLineNumbers => {
+                pager.line_numbers = !pager.line_numbers;
+                pager.clear();
+            }
             Prompt::CloseGrep => greps.close_grep(),
+            Prompt::NextFile => {
+                files.select_next_file();
+                pager.clear();
+            }
+            Prompt::PrevFile => {
+                files.select_prev_file();
+                pager.clear();
+            }
             Prompt::SingleLineDown => {
-                let last_index = greps.current_grep().lines.len() - 1;
-                if index < last_index - printed_lines + 2 {
+                let lines_count = greps.lines_count();
+                if index + printed_lines < lines_count + 1 {
                     greps.change_current_line_index(index + 1)
                 }
             }
@@ -57,13 +238,70 @@ fn main() {
             Prompt::ScrollTop => greps.change_current_line_index(0),
             Prompt::NextPage => greps.change_current_line_index(index + printed_lines),
             Prompt::ScrollBottom => {
-                let last_index = greps.current_grep().lines.len() - printed_lines + 1;
+                let last_index = bottom_line_index(greps, printed_lines);
                 greps.change_current_line_index(last_index);
             }
-            Prompt::NextSearch => greps.next_search(),
-            Prompt::PrevSearch => greps.prev_search(),
-            //_ => {}
+            Prompt::NextSearch => report_search(&mut pager, greps.next_search(), true),
+            Prompt::PrevSearch => report_search(&mut pager, greps.prev_search(), false),
+            Prompt::Refresh => redraw = pager.expire_message(),
+            Prompt::Goto(number, file) => {
+                let source = match file {
+                    Some(ref name) => sources.iter().position(|source| source == name),
+                    None => Some(greps.top_source()),
+                };
+                match source.map(|source| greps.find_line(source, number, 0)) {
+                    Some(Some(idx)) => {
+                        pager.clear();
+                        greps.change_current_line_index(idx);
+                    }
+                    Some(None) => pager.warn(format!("No line {} here", number)),
+                    None => pager.error(format!("goto: no file {} here", file.unwrap_or_default())),
+                }
+            }
+            Prompt::OpenFile(name) => {
+                match files.add_file(&name, &options) {
+                    Ok(()) => pager.clear(),
+                    Err(err) => pager.error(format!("open {}: {}", name, err)),
+                }
+            }
+            Prompt::Color(name, decoration) => {
+                for file in &mut files.files {
+                    file.greps.decorations.insert(name.clone(), decoration.clone());
+                }
+            }
+            Prompt::Set(setting, value) => {
+                let on = match setting {
+                    Setting::Wrap => {
+                        if value != Some(pager.wrap) {
+                            pager.toggle_wrap();
+                        }
+                        pager.wrap
+                    }
+                    Setting::LineNumbers => {
+                        pager.line_numbers = value.unwrap_or(!pager.line_numbers);
+                        pager.line_numbers
+                    }
+                    Setting::WrapSearch => {
+                        let on = value.unwrap_or(!greps.wrap_search);
+                        for file in &mut files.files {
+                            file.greps.wrap_search = on;
+                        }
+                        on
+                    }
+                };
+                pager.clear();
+                pager.info(format!("{} is {}", setting.name(), if on { "on" } else { "off" }));
+            }
+            Prompt::SaveLines(path) => {
+                match save_lines(greps, &path) {
+                    Ok(()) => {
+                        pager.info(format!("Saved {} lines to {}", greps.lines_count(), path))
+                    }
+                    Err(err) => pager.error(format!("save {}: {}", path, err)),
+                }
+            }
+            Prompt::Help(text) => pager.info(text),
+            Prompt::CommandError(err) => pager.error(err),
         }
-        pager.mv_cursor((0, 0));
     }
-}
\ No newline at end of file
+}
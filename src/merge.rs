@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use regex::{Captures, Regex};
+
+use input::{Chunk, LineStream};
+
+/// How long the merge waits for a source that has nothing buffered before
+/// going on without it, so an idle followed file doesn't stall the others.
+const SOURCE_WAIT_MS: u64 = 500;
+
+/// Only the beginning of a line is searched for its timestamp.
+const TIMESTAMP_SEARCH_LEN: usize = 128;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+                            "Nov", "Dec"];
+
+/// Finds timestamps in log lines and turns them into sortable numbers.
+///
+/// Known formats are ISO 8601 (`2017-05-02 13:37:00.123`, `2017-05-02T13:37:00,123`),
+/// syslog (`May  2 13:37:00`) and a bare time of day (`13:37:00.123`). Parts
+/// missing from a format, like the syslog year, count as zero.
+pub struct TimestampParser {
+    iso: Regex,
+    syslog: Regex,
+    time: Regex,
+}
+
+impl TimestampParser {
+    pub fn new() -> TimestampParser {
+        TimestampParser {
+            iso: Regex::new(r"(\d{4})-(\d{2})-(\d{2})[T ](\d{2}):(\d{2}):(\d{2})(?:[.,](\d{1,9}))?")
+                .unwrap(),
+            syslog: Regex::new(r"\b(Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec) +(\d{1,2}) (\d{2}):(\d{2}):(\d{2})")
+                .unwrap(),
+            time: Regex::new(r"\b(\d{2}):(\d{2}):(\d{2})(?:[.,](\d{1,9}))?").unwrap(),
+        }
+    }
+
+    pub fn parse(&self, line: &str) -> Option<u64> {
+        let mut end = line.len().min(TIMESTAMP_SEARCH_LEN);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        let line = &line[..end];
+        if let Some(caps) = self.iso.captures(line) {
+            let date = (number(&caps, 1), number(&caps, 2), number(&caps, 3));
+            let time = (number(&caps, 4), number(&caps, 5), number(&caps, 6));
+            return Some(timestamp(date, time, text(&caps, 7)));
+        }
+        if let Some(caps) = self.syslog.captures(line) {
+            let month = MONTHS.iter().position(|&m| Some(m) == text(&caps, 1)).unwrap_or(0);
+            let date = (0, month as u64 + 1, number(&caps, 2));
+            let time = (number(&caps, 3), number(&caps, 4), number(&caps, 5));
+            return Some(timestamp(date, time, None));
+        }
+        if let Some(caps) = self.time.captures(line) {
+            let time = (number(&caps, 1), number(&caps, 2), number(&caps, 3));
+            return Some(timestamp((0, 0, 0), time, text(&caps, 4)));
+        }
+        None
+    }
+}
+
+fn text<'t>(caps: &Captures<'t>, idx: usize) -> Option<&'t str> {
+    caps.get(idx).map(|m| m.as_str())
+}
+
+fn number(caps: &Captures, idx: usize) -> u64 {
+    text(caps, idx).and_then(|t| t.parse::<u64>().ok()).unwrap_or(0)
+}
+
+/// Microseconds since a made up epoch, good enough for ordering.
+fn timestamp(date: (u64, u64, u64), time: (u64, u64, u64), fraction: Option<&str>) -> u64 {
+    let (year, month, day) = date;
+    let (hours, minutes, seconds) = time;
+    let days = (year * 12 + month) * 31 + day;
+    let seconds = ((days * 24 + hours) * 60 + minutes) * 60 + seconds;
+    let micros = fraction.map_or(0, |fraction| {
+        let digits = fraction.chars().chain("000000".chars()).take(6).collect::<String>();
+        digits.parse::<u64>().unwrap_or(0)
+    });
+    seconds * 1_000_000 + micros
+}
+
+struct Source {
+    stream: LineStream,
+    /// Chunks received and not merged yet, with the timestamp of each of
+    /// their lines.
+    pending: VecDeque<Chunk>,
+    timestamps: VecDeque<u64>,
+    /// Lines of the first pending chunk merged already.
+    merged: usize,
+    last_timestamp: u64,
+    finished: bool,
+}
+
+impl Source {
+    /// Lines without a timestamp, like stack traces, keep the one of the line
+    /// before them, so they stay together.
+    fn push(&mut self, mut chunk: Chunk, index: usize, parser: &TimestampParser) {
+        for idx in 0..chunk.len() {
+            if let Some(timestamp) = parser.parse(chunk.line(idx)) {
+                self.last_timestamp = timestamp;
+            }
+            self.timestamps.push_back(self.last_timestamp);
+        }
+        if !chunk.is_empty() {
+            chunk.source = index;
+            self.pending.push_back(chunk);
+        }
+    }
+
+    /// Takes the next line, and the ones after it in its chunk while they
+    /// come before `bound`.
+    fn take_before(&mut self, bound: Option<(u64, usize)>, index: usize) -> Chunk {
+        let chunk_len = self.pending[0].len();
+        let begin = self.merged;
+        let mut end = begin + 1;
+        self.timestamps.pop_front();
+        while end < chunk_len &&
+              bound.is_none_or(|bound| (self.timestamps[0], index) < bound) {
+            self.timestamps.pop_front();
+            end += 1;
+        }
+        if end == chunk_len {
+            self.merged = 0;
+            let chunk = self.pending.pop_front().unwrap();
+            if begin == 0 {
+                chunk
+            } else {
+                chunk.lines(begin..end)
+            }
+        } else {
+            self.merged = end;
+            self.pending[0].lines(begin..end)
+        }
+    }
+}
+
+/// Interleaves lines of all streams by their timestamps, chunks carry the
+/// index of their stream in `Chunk::source`.
+pub fn merge(streams: Vec<LineStream>) -> LineStream {
+    LineStream::spawn_checked(move |sender, binary| merge_sources(streams, sender, binary))
+}
+
+fn merge_sources(streams: Vec<LineStream>, sender: &Sender<Chunk>, binary: &AtomicBool) {
+    let parser = TimestampParser::new();
+    let mut sources = streams.into_iter()
+        .map(|stream| {
+                 Source {
+                     stream,
+                     pending: VecDeque::new(),
+                     timestamps: VecDeque::new(),
+                     merged: 0,
+                     last_timestamp: 0,
+                     finished: false,
+                 }
+             })
+        .collect::<Vec<_>>();
+    let wait = Duration::from_millis(SOURCE_WAIT_MS);
+    loop {
+        let mut idle = vec![false; sources.len()];
+        for (index, source) in sources.iter_mut().enumerate() {
+            if source.finished || !source.pending.is_empty() {
+                continue;
+            }
+            match source.stream.recv_timeout(wait) {
+                Ok(chunk) => {
+                    if source.stream.is_binary() {
+                        binary.store(true, Ordering::Relaxed);
+                    }
+                    source.push(chunk, index, &parser)
+                }
+                Err(RecvTimeoutError::Timeout) => idle[index] = true,
+                Err(RecvTimeoutError::Disconnected) => source.finished = true,
+            }
+        }
+        if sources.iter().all(|s| s.finished && s.pending.is_empty()) {
+            return;
+        }
+
+        // A source that may still deliver earlier lines has to be refilled
+        // before anything newer is sent.
+        loop {
+            let waiting = sources.iter()
+                .enumerate()
+                .any(|(index, s)| s.pending.is_empty() && !s.finished && !idle[index]);
+            if waiting {
+                break;
+            }
+            let mut next = sources.iter()
+                .enumerate()
+                .filter_map(|(index, s)| s.timestamps.front().map(|&timestamp| (timestamp, index)))
+                .collect::<Vec<_>>();
+            next.sort();
+            match next.first() {
+                Some(&(_, index)) => {
+                    let chunk = sources[index].take_before(next.get(1).cloned(), index);
+                    if sender.send(chunk).is_err() {
+                        return;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[test]
+fn parse_timestamps() {
+    let parser = TimestampParser::new();
+
+    assert!(parser.parse("2017-05-02 13:37:00.5 INF") > parser.parse("2017-05-02T13:37:00,123"));
+    assert!(parser.parse("2017-05-02 13:37:00 INF") > parser.parse("2017-04-30 23:59:59 INF"));
+    assert!(parser.parse("May  2 13:37:00 host") > parser.parse("Apr 30 23:59:59 host"));
+    assert!(parser.parse("[13:37:01] ready") > parser.parse("[13:37:00.999] ready"));
+    assert_eq!(parser.parse("no time here"), None);
+}
+
+#[test]
+fn merge_by_timestamp() {
+    let client = LineStream::spawn(|sender| {
+        let _ = sender.send(Chunk::from("10:00:01 client a\n  at frame\n10:00:04 client b"));
+    });
+    let server = LineStream::spawn(|sender| {
+        let _ = sender.send(Chunk::from("10:00:02 server a\n10:00:03 server b"));
+    });
+    let merged = merge(vec![client, server]);
+
+    let mut lines = Vec::new();
+    while let Ok(chunk) = merged.recv_timeout(Duration::from_secs(5)) {
+        lines.extend((0..chunk.len()).map(|idx| (chunk.source, chunk.line(idx))));
+    }
+    assert_eq!(lines,
+               vec![(0, "10:00:01 client a"),
+                    (0, "  at frame"),
+                    (1, "10:00:02 server a"),
+                    (1, "10:00:03 server b"),
+                    (0, "10:00:04 client b")]);
+}
@@ -0,0 +1,335 @@
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::File;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use encoding::Encoding;
+use expression::{Case, MatchMode};
+use input;
+use utils;
+
+const USAGE: &str = "Usage:
+    rustgrepper [options] [FILE...]
+    <command> | rustgrepper [options]
+
+Options:
+    -g, --grep PATTERN          open a grep of PATTERN, repeat to chain greps,
+                                PATTERN may start with -A N, -B N or -C N
+                                for lines of context after, before or
+                                around matches, then with ! for lines that
+                                don't match
+                                PATTERN starting with -x combines regexes
+                                with and, or, not and parentheses, e.g.
+                                '-x (timeout or refused) and not retry',
+                                quote regexes with spaces or parentheses
+    -p, --search PATTERN        search for PATTERN
+    -i, --ignore-case           match patterns regardless of case
+    -S, --smart-case            ignore case unless a pattern has uppercase
+                                letters
+    -s, --case-sensitive        match the case of patterns (default)
+    -F, --fixed-strings         match patterns as plain text, not regexes
+        --no-wrap-search        stop at the last search match instead of
+                                going on from the first one
+    -c, --color NAME=REGEX:ATTR color matches of REGEX with comma separated
+                                attributes: red, blue, green, yellow,
+                                magenta, cyan, inverse or none
+    -f, --follow                keep reading data appended to files
+    -m, --merge                 interleave all files by their timestamps
+    -W, --no-wrap               cut long lines instead of wrapping them
+    -N, --line-numbers          show the number lines have in their file
+    -r, --rotated               prepend rotated siblings of each file, like
+                                app.log.2.gz and app.log.1 for app.log
+    -e, --encoding NAME         decode files as utf-8 (default), latin-1,
+                                utf-16le or utf-16be, bytes that can't be
+                                decoded are shown as \\xNN
+        --config PATH           read options from PATH instead of
+                                $XDG_CONFIG_HOME/rustgrepper/config
+    -n, --line N                start at line N of the file shown first
+    -h, --help                  print this help
+    -V, --version               print version
+
+With several files Tab, or the `next` and `prev` commands, switch between them.
+`:` or `#` reads a command, Tab completes it, e.g. `goto 1200`, `open FILE`,
+`color NAME REGEX red`, `set wrap off`, `save out.log`, `grep PATTERN`.
+`help` lists them all.
+[ and ] select the grep to the left or right, so do Left and Right unless
+lines are cut, `w` toggles it, then Left and Right scroll them sideways.
+`!` opens a grep of lines that don't match. `l` toggles line numbers. `p`
+and `P` go from a grep to the same line in the grep it was derived from or
+in ROOT.
+
+Up and Down recall what was typed before at the same prompt, Ctrl-R searches
+it. The history is kept in $XDG_DATA_HOME/rustgrepper/history. Tab completes
+the word being typed from words of the lines, the most frequent first, and
+goes through the other candidates when pressed again.
+
+Patterns typed in the pager may start with the same -i, -S, -s or -F flags
+to match differently from these options, and greps with -x to read an
+expression.
+
+The config file holds one option per line, with its long name and without
+dashes, e.g. `color errors=ERR|error:red`. Lines starting with # are ignored.";
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub files: Vec<String>,
+    pub greps: Vec<String>,
+    pub search: Option<String>,
+    pub colors: Vec<(String, utils::DecorationPattern)>,
+    pub follow: bool,
+    pub merge: bool,
+    pub wrap: bool,
+    pub line_numbers: bool,
+    pub rotated: bool,
+    pub encoding: Encoding,
+    pub mode: MatchMode,
+    pub wrap_search: bool,
+    pub config: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Options {
+    /// Defaults, including the decorations used when neither the config nor
+    /// the command line overrides them.
+    pub fn new() -> Options {
+        Options {
+            files: Vec::new(),
+            greps: Vec::new(),
+            search: None,
+            colors: vec![(String::from("errors"),
+                          utils::DecorationPattern::from_single_attr(utils::Attribute::Red,
+                                                                     "ERR|error")),
+                         (String::from("informations"),
+                          utils::DecorationPattern::from_single_attr(utils::Attribute::Blue,
+                                                                     "INF|INFO|inf|info"))],
+            follow: false,
+            merge: false,
+            wrap: true,
+            line_numbers: false,
+            rotated: false,
+            encoding: Encoding::Utf8,
+            mode: MatchMode::new(),
+            wrap_search: true,
+            config: None,
+            line: None,
+            help: false,
+            version: false,
+        }
+    }
+
+    /// Applies `--name value` pairs and positional arguments in order.
+    fn apply(&mut self, args: &[String]) -> Result<(), String> {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                self.files.push(arg.clone());
+                continue;
+            }
+            let (name, inline_value) = match arg.find('=') {
+                Some(idx) if arg.starts_with("--") => (&arg[..idx], Some(arg[idx + 1..].to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || -> Result<String, String> {
+                match inline_value.clone() {
+                    Some(value) => Ok(value),
+                    None => args.next().cloned().ok_or_else(|| format!("{} requires a value", name)),
+                }
+            };
+            match name {
+                "-g" | "--grep" => self.greps.push(value()?),
+                "-p" | "--search" => self.search = Some(value()?),
+                "-c" | "--color" => {
+                    let (name, decoration) = parse_color(&value()?)?;
+                    self.colors.retain(|(n, _)| *n != name);
+                    self.colors.push((name, decoration));
+                }
+                "-f" | "--follow" => self.follow = true,
+                "-m" | "--merge" => self.merge = true,
+                "-W" | "--no-wrap" => self.wrap = false,
+                "-N" | "--line-numbers" => self.line_numbers = true,
+                "-r" | "--rotated" => self.rotated = true,
+                "-i" | "--ignore-case" => self.mode.case = Case::Insensitive,
+                "-S" | "--smart-case" => self.mode.case = Case::Smart,
+                "-s" | "--case-sensitive" => self.mode.case = Case::Sensitive,
+                "-F" | "--fixed-strings" => self.mode.literal = true,
+                "--no-wrap-search" => self.wrap_search = false,
+                "-e" | "--encoding" => {
+                    let value = value()?;
+                    match Encoding::from_name(&value) {
+                        Some(encoding) => self.encoding = encoding,
+                        None => return Err(format!("unknown encoding: {}", value)),
+                    }
+                }
+                "--config" => self.config = Some(PathBuf::from(value()?)),
+                "-n" | "--line" => {
+                    let value = value()?;
+                    match value.parse::<usize>() {
+                        Ok(line) if line > 0 => self.line = Some(line),
+                        _ => return Err(format!("invalid line number: {}", value)),
+                    }
+                }
+                "-h" | "--help" => self.help = true,
+                "-V" | "--version" => self.version = true,
+                _ => return Err(format!("unknown option: {}", name)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Options from the config file, overridden by the command line.
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut cli = Options::new();
+        cli.apply(args)?;
+
+        let mut options = Options::new();
+        let config = cli.config.clone().or_else(default_config_path);
+        if let Some(path) = config {
+            match File::open(&path) {
+                Ok(file) => {
+                    let args = config_args(BufReader::new(file))
+                        .map_err(|err| format!("{}: {}", path.display(), err))?;
+                    options.apply(&args).map_err(|err| format!("{}: {}", path.display(), err))?;
+                }
+                Err(err) => {
+                    if cli.config.is_some() {
+                        return Err(format!("{}: {}", path.display(), err));
+                    }
+                }
+            }
+        }
+        options.apply(args)?;
+        Ok(options)
+    }
+}
+
+/// `NAME=REGEX:ATTR[,ATTR]`, the regex itself may contain both `=` and `:`.
+fn parse_color(spec: &str) -> Result<(String, utils::DecorationPattern), String> {
+    let invalid = || format!("invalid color {}, expected NAME=REGEX:ATTR", spec);
+    let name_end = spec.find('=').ok_or_else(invalid)?;
+    let attrs_begin = spec.rfind(':').ok_or_else(invalid)?;
+    if attrs_begin < name_end || name_end == 0 {
+        return Err(invalid());
+    }
+    let mut attributes = Vec::new();
+    for attr in spec[attrs_begin + 1..].split(',') {
+        match utils::Attribute::from_name(attr.trim()) {
+            Some(attr) => attributes.push(attr),
+            None => return Err(format!("unknown color attribute: {}", attr)),
+        }
+    }
+    Ok((spec[..name_end].to_string(),
+        utils::DecorationPattern::new(&spec[name_end + 1..attrs_begin], attributes)))
+}
+
+fn config_args<R: BufRead>(reader: R) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.find(char::is_whitespace) {
+            Some(idx) => {
+                args.push(format!("--{}", &line[..idx]));
+                args.push(line[idx..].trim_start().to_string());
+            }
+            None => args.push(format!("--{}", line)),
+        }
+    }
+    Ok(args)
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("rustgrepper").join("config"))
+}
+
+/// Parses the process arguments, exits on errors, `--help` and `--version`.
+pub fn from_env() -> Options {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("rustgrepper: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        process::exit(0);
+    }
+    if options.version {
+        println!("rustgrepper {}", env!("CARGO_PKG_VERSION"));
+        process::exit(0);
+    }
+    if options.files.is_empty() && input::stdin_is_tty() {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    options
+}
+
+#[cfg(test)]
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn parse_options() {
+    let mut options = Options::new();
+    options.apply(&args(&["-g", "ERR", "--grep=db", "app.log", "--line", "12", "-f", "-W", "-N"]))
+        .unwrap();
+
+    assert_eq!(options.greps, vec!["ERR", "db"]);
+    assert_eq!(options.files, vec!["app.log"]);
+    assert_eq!(options.line, Some(12));
+    assert!(options.follow);
+    assert!(!options.wrap);
+    assert!(options.line_numbers);
+    assert_eq!(options.mode, MatchMode::new());
+    options.apply(&args(&["-S", "-F", "--no-wrap-search", "-p", "timeout"])).unwrap();
+    assert!(!options.wrap_search);
+    assert_eq!(options.mode.case, Case::Smart);
+    assert!(options.mode.literal);
+    assert_eq!(options.search, Some("timeout".to_string()));
+    options.apply(&args(&["--case-sensitive"])).unwrap();
+    assert_eq!(options.mode.case, Case::Sensitive);
+    options.apply(&args(&["--encoding", "Latin-1"])).unwrap();
+    assert_eq!(options.encoding, Encoding::Latin1);
+    assert!(options.apply(&args(&["-e", "ebcdic"])).is_err());
+    assert!(options.apply(&args(&["--search"])).is_err());
+    assert!(options.apply(&args(&["--bogus"])).is_err());
+}
+
+#[test]
+fn parse_color_options() {
+    let mut options = Options::new();
+    options.apply(&args(&["--color", "errors=a=b:c:red,inverse", "-c", "ids=[0-9]+:blue"]))
+        .unwrap();
+
+    assert_eq!(options.colors[1],
+               (String::from("errors"),
+                utils::DecorationPattern::new("a=b:c",
+                                              vec![utils::Attribute::Red,
+                                                   utils::Attribute::Inverse])));
+    assert_eq!(options.colors[2].0, "ids");
+    assert!(parse_color("errors:red").is_err());
+    assert!(parse_color("errors=ERR:purple").is_err());
+}
+
+#[test]
+fn config_file_options() {
+    let config = "# defaults\nfollow\ncolor  warnings=WARN:red\n\nsearch timeout error\n";
+    let options = config_args(config.as_bytes()).unwrap();
+
+    assert_eq!(options,
+               args(&["--follow", "--color", "warnings=WARN:red", "--search", "timeout error"]));
+}
@@ -1,3 +1,8 @@
+use unicode_width::UnicodeWidthChar;
+
+/// Columns between tab stops.
+pub const TAB_WIDTH: usize = 8;
+
 #[allow(dead_code)]
 #[derive(Debug, Copy, Clone)]
 pub enum Key {
@@ -22,36 +27,126 @@ pub enum Key {
     Esc,
 }
 
+/// How much a message to the user matters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
 pub trait TermOperations {
     fn term_size(&mut self) -> (usize, usize);
-    fn print(&mut self, &str);
+    fn print(&mut self, text: &str);
     fn clear(&mut self);
-    fn mv_cursor(&mut self, (usize, usize));
+    fn mv_cursor(&mut self, pos: (usize, usize));
     fn cursor_pos(&mut self) -> (usize, usize);
     fn clear_line(&mut self);
-    fn clear_line_from(&mut self, (usize, usize));
+    fn clear_line_from(&mut self, pos: (usize, usize));
     fn input_key(&mut self) -> Key;
 
+    /// Prints `text` so that it stands out, like a selection.
+    fn print_highlighted(&mut self, text: &str) {
+        self.print(text);
+    }
+
+    /// Prints `buffer` at the cursor, wrapped and cut off above the bottom
+    /// `offset` rows. The very last cell stays empty, writing it would move
+    /// the cursor past them.
     fn print_buffer(&mut self, buffer: &str, offset: usize) {
-        let end_height = self.term_size().1 - offset;
-        let buffer_len = buffer.len();
-        let lines_to_end = end_height - self.cursor_pos().1;
-        let limit = lines_to_end * self.term_size().0 - self.cursor_pos().0;
-        if buffer_len > limit {
-            self.print(&buffer[..(limit) - 1]);
-        } else {
-            self.print(buffer);
-        }
+        let (width, height) = self.term_size();
+        let (x, y) = self.cursor_pos();
+        let end_height = height.saturating_sub(offset);
+        let cells = (end_height.saturating_sub(y) * width).saturating_sub(x + 1);
+        self.print(&fit_to_cells(buffer, x, width, cells));
     }
 
+    /// Part of `line` that doesn't fit on a single row.
+    #[allow(unused)]
     fn top_leftofer<'a>(&mut self, line: &'a str) -> Option<&'a str> {
         let (max_x, _) = self.term_size();
-        if line.len() <= max_x {
-            None
+        let mut column = 0;
+        for (idx, ch) in line.char_indices() {
+            column += char_width(ch, column);
+            if column > max_x {
+                return Some(&line[idx..]);
+            }
+        }
+        None
+    }
+}
+
+/// Part of `text`, which begins at display `column`, that lies between
+/// columns `from` and `to`, along with the column `text` ends at.
+///
+/// Tabs become spaces, so does the visible part of a wide character cut by
+/// either bound.
+pub fn column_slice(text: &str, column: usize, from: usize, to: usize) -> (String, usize) {
+    let mut slice = String::new();
+    let mut column = column;
+    for ch in text.chars() {
+        let begin = column;
+        column += char_width(ch, column);
+        if column <= from || begin >= to {
+            continue;
+        }
+        if begin < from || column > to || ch == '\t' {
+            slice.push_str(&" ".repeat(column.min(to) - begin.max(from)));
+        } else if ch.width().is_none() {
+            slice.push(::std::char::REPLACEMENT_CHARACTER);
         } else {
-            Some(&line[max_x..])
+            slice.push(ch);
         }
     }
+    (slice, column)
+}
+
+/// Terminal columns taken by `ch` printed at `column`, tabs reach up to the
+/// next tab stop.
+pub fn char_width(ch: char, column: usize) -> usize {
+    match ch {
+        '\t' => TAB_WIDTH - column % TAB_WIDTH,
+        _ => ch.width().unwrap_or(1),
+    }
+}
+
+/// Lays `text` out on rows of `width` columns, starting at `column`, and
+/// keeps what fits in `cells` terminal cells.
+///
+/// Tabs become spaces and characters the terminal can't print become U+FFFD.
+/// A wide character that doesn't fit at the end of a row is moved to the next
+/// one, like the terminal would do, with the row padded.
+pub fn fit_to_cells(text: &str, column: usize, width: usize, cells: usize) -> String {
+    let mut fitted = String::with_capacity(text.len());
+    if width == 0 {
+        return fitted;
+    }
+    let mut column = column % width;
+    let mut used = 0;
+    for ch in text.chars() {
+        let mut ch_width = char_width(ch, column).min(width - column);
+        if ch != '\t' && ch_width < char_width(ch, column) {
+            let padding = width - column;
+            if used + padding >= cells {
+                break;
+            }
+            fitted.push_str(&" ".repeat(padding));
+            used += padding;
+            column = 0;
+            ch_width = char_width(ch, column);
+        }
+        if used + ch_width > cells {
+            break;
+        }
+        match ch {
+            '\t' => fitted.push_str(&" ".repeat(ch_width)),
+            _ if ch.width().is_none() => fitted.push(::std::char::REPLACEMENT_CHARACTER),
+            _ => fitted.push(ch),
+        }
+        used += ch_width;
+        column = (column + ch_width) % width;
+    }
+    fitted
 }
 
 #[allow(dead_code)]
@@ -120,4 +215,31 @@ fn top_leftofer_test_overflow() {
         .collect::<String>();
     assert_eq!(test_line.len(), 11);
     assert_eq!(&pager.top_leftofer(&test_line), &Some("a"));
-}
\ No newline at end of file
+}
+#[test]
+fn top_leftofer_test_wide_chars() {
+    let mut pager = PagerMock::default().with_size((10, 10)).with_cursor_pos((0, 0));
+    assert_eq!(pager.top_leftofer("日本語のログ行"), Some("グ行"));
+    assert_eq!(pager.top_leftofer("日本語のa"), None);
+    assert_eq!(pager.top_leftofer("ab\tcde"), Some("e"));
+}
+
+#[test]
+fn column_slice_test() {
+    assert_eq!(column_slice("0123456789", 0, 3, 6), ("345".to_string(), 10));
+    assert_eq!(column_slice("6789", 6, 3, 8), ("67".to_string(), 10));
+    assert_eq!(column_slice("012", 0, 5, 8), (String::new(), 3));
+    assert_eq!(column_slice("a\tb", 0, 4, 9), ("    b".to_string(), 9));
+    assert_eq!(column_slice("日本語", 0, 1, 5), (" 本 ".to_string(), 6));
+}
+
+#[test]
+fn fit_to_cells_test() {
+    assert_eq!(fit_to_cells("a\tb", 0, 20, 100), "a       b");
+    assert_eq!(fit_to_cells("\tb", 3, 20, 100), "     b");
+    assert_eq!(fit_to_cells("zażółć", 0, 20, 4), "zażó");
+    // The wide character goes to the next row, the first one is padded.
+    assert_eq!(fit_to_cells("abc日本", 0, 4, 100), "abc 日本");
+    assert_eq!(fit_to_cells("abc日本", 0, 4, 7), "abc 日");
+    assert_eq!(fit_to_cells("a\u{85}b", 0, 20, 100), "a\u{fffd}b");
+}
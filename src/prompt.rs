@@ -1,4 +1,14 @@
+use unicode_width::UnicodeWidthStr;
+
 use pager::*;
+use editor::*;
+use history::*;
+use commands::{self, Setting};
+use utils::DecorationPattern;
+use vocabulary;
+
+/// Candidates listed at once above the status bar.
+const POPUP_HEIGHT: usize = 8;
 
 #[allow(unused)]
 pub enum Prompt {
@@ -12,39 +22,75 @@ pub enum Prompt {
     GrepPattern(String),
     GrepLeft,
     GrepRight,
+    ParentGrep,
+    RootGrep,
+    ScrollLeft,
+    ScrollRight,
+    ToggleWrap,
+    ToggleLineNumbers,
     CloseGrep,
+    NextFile,
+    PrevFile,
     NextSearch,
     PrevSearch,
+    Refresh,
+    /// Go to the line with this number in the named file, of merged or
+    /// rotated ones, or in the file of the line on top.
+    Goto(usize, Option<String>),
+    OpenFile(String),
+    Color(String, DecorationPattern),
+    /// Turn a setting on, off, or over when no value is given.
+    Set(Setting, Option<bool>),
+    /// Write the lines of the current grep to a file.
+    SaveLines(String),
+    Help(String),
+    CommandError(String),
 }
 
 pub enum PromptMode {
     Visual,
     Search,
     Grep,
+    /// A grep of lines that don't match the pattern.
+    InvertedGrep,
     Command,
 }
 
-pub fn prompt<P>(pager: &mut P, mode: PromptMode) -> Prompt
-    where P: TermOperations
-{
-    let mut typed = String::from("");
-    let mut tabbed = String::from("");
+/// What a prompt needs from the lines shown behind it.
+pub trait PromptContext<P> {
+    /// Called with the pattern of a search after each change while it is
+    /// typed, and with `None` when the search is cancelled.
+    fn preview(&mut self, pager: &mut P, typed: Option<&str>);
 
-    pager.clear_line();
-    match mode {
-        PromptMode::Visual => {
-            pager.print(":");
-        }
-        PromptMode::Search => {
-            pager.print("/");
-        }
-        PromptMode::Grep => {
-            pager.print("&/");
-        }
-        PromptMode::Command => {
-            pager.print("#");
-        }
-    }
+    /// Prints the lines and the status bar again.
+    fn redraw(&mut self, pager: &mut P);
+
+    /// Words of the lines that `prefix` may be completed to, the most
+    /// frequent first.
+    fn words(&mut self, prefix: &str) -> Vec<String>;
+}
+
+/// Reads keys until they make up an action. Lines typed are added to
+/// `history`.
+pub fn prompt<P, C>(pager: &mut P,
+                    mode: PromptMode,
+                    history: &mut History,
+                    context: &mut C)
+                    -> Prompt
+    where P: TermOperations,
+          C: PromptContext<P>
+{
+    let (prefix, typed, kind) = match mode {
+        PromptMode::Visual => (":", "", HistoryKind::Command),
+        PromptMode::Search => ("/", "", HistoryKind::Search),
+        PromptMode::Grep => ("&/", "", HistoryKind::Grep),
+        PromptMode::InvertedGrep => ("&/", "!", HistoryKind::Grep),
+        PromptMode::Command => ("#", "", HistoryKind::Command),
+    };
+    let mut editor = LineEditor::new(typed);
+    let mut recall = Recall::default();
+    let mut completion: Option<Completion> = None;
+    editor.render(pager, prefix);
     loop {
         match mode {
             PromptMode::Visual => {
@@ -56,13 +102,16 @@ pub fn prompt<P>(pager: &mut P, mode: PromptMode) -> Prompt
                             }
                             'q' => return Prompt::Exit,
                             '/' => {
-                                return prompt(pager, PromptMode::Search);
+                                return prompt(pager, PromptMode::Search, history, context);
                             }
                             '&' => {
-                                return prompt(pager, PromptMode::Grep);
+                                return prompt(pager, PromptMode::Grep, history, context);
+                            }
+                            '!' => {
+                                return prompt(pager, PromptMode::InvertedGrep, history, context);
                             }
-                            '#' => {
-                                return prompt(pager, PromptMode::Command);
+                            '#' | ':' => {
+                                return prompt(pager, PromptMode::Command, history, context);
                             }
                             'g' => {
                                 return Prompt::ScrollTop;
@@ -76,73 +125,215 @@ pub fn prompt<P>(pager: &mut P, mode: PromptMode) -> Prompt
                             'N' => {
                                 return Prompt::PrevSearch;
                             },
-                            c => {
-                                pager.print(&format!("CH: {} code: {} ", c, c as u8));
-                            },
+                            '[' => return Prompt::GrepLeft,
+                            ']' => return Prompt::GrepRight,
+                            'p' => return Prompt::ParentGrep,
+                            'P' => return Prompt::RootGrep,
+                            'w' => return Prompt::ToggleWrap,
+                            'l' => return Prompt::ToggleLineNumbers,
+                            _ => {}
                         }
                     },
                     Key::Down => return Prompt::SingleLineDown,
                     Key::Up => return Prompt::SingleLineUp,
-                    Key::Left => return Prompt::GrepLeft, 
-                    Key::Right => return Prompt::GrepRight,
-                    Key::Ctrl(ch) => {
-                        match ch {
-                            'w' => return Prompt::CloseGrep,
-                            _ => {}
-                        }
-                    },
+                    Key::Left => return Prompt::ScrollLeft,
+                    Key::Right => return Prompt::ScrollRight,
+                    Key::Tab => return Prompt::NextFile,
+                    Key::Ctrl('w') => return Prompt::CloseGrep,
+                    Key::Null => return Prompt::Refresh,
                     _ => {}
                 }
             }
-            PromptMode::Search | PromptMode::Grep => {
-                match pager.input_key() {
+            PromptMode::Search | PromptMode::Grep | PromptMode::InvertedGrep => {
+                let key = pager.input_key();
+                if !matches!(key, Key::Tab | Key::Null) && completion.take().is_some() {
+                    context.redraw(pager);
+                    editor.render(pager, prefix);
+                }
+                match key {
                     Key::Esc => {
-                        return prompt(pager, PromptMode::Visual);
+                        if let PromptMode::Search = mode {
+                            context.preview(pager, None);
+                        }
+                        return prompt(pager, PromptMode::Visual, history, context);
+                    }
+                    Key::Tab => {
+                        let words = |text: &str| {
+                            let begin = vocabulary::word_start(text);
+                            (begin, context.words(&text[begin..]))
+                        };
+                        completion = complete(pager, &mut editor, completion.take(), prefix, words);
                     }
                     Key::Enter => {
+                        let typed = editor.text().to_string();
+                        history.add(kind, &typed);
                         match mode {
                             PromptMode::Search => return Prompt::SearchPattern(typed),
-                            PromptMode::Grep => return Prompt::GrepPattern(typed),
+                            PromptMode::Grep | PromptMode::InvertedGrep => {
+                                return Prompt::GrepPattern(typed)
+                            }
                             _ => {}
                         }
                     }
-                    Key::Char(ch) => {
-                        typed.push(ch);
-                        pager.print(&format!("{}", ch));
+                    _ => {
+                        let entries = history.entries(kind);
+                        if edit_line(pager, &mut editor, &mut recall, entries, key, prefix) &&
+                           matches!(mode, PromptMode::Search) {
+                            context.preview(pager, Some(editor.text()));
+                            editor.render(pager, prefix);
+                        }
                     }
-                    _ => {}
                 }
             }
             PromptMode::Command => {
-                match pager.input_key(){
+                let key = pager.input_key();
+                if !matches!(key, Key::Tab | Key::Null) && completion.take().is_some() {
+                    context.redraw(pager);
+                    editor.render(pager, prefix);
+                }
+                match key {
                     Key::Esc => {
-                        return prompt(pager, PromptMode::Visual);
+                        return prompt(pager, PromptMode::Visual, history, context);
                     }
                     Key::Tab => {
-                        // find in commands, since is only one return close ^^
-                        tabbed = "close".to_string();
-                        pager.clear_line();
-                        pager.print(&format!("#{}", tabbed));
+                        completion = complete(pager,
+                                              &mut editor,
+                                              completion.take(),
+                                              prefix,
+                                              commands::complete);
                     }
                     Key::Enter => {
-                        let result = if tabbed == "".to_string() {
-                            typed.clone()
-                        } else {
-                            tabbed.clone()
+                        history.add(kind, editor.text());
+                        return match commands::parse(editor.text()) {
+                            Ok(command) => command,
+                            Err(err) => Prompt::CommandError(err),
                         };
-                        match result.as_ref() {
-                            "close" => return Prompt::CloseGrep,
-                            _ => {}
-                        }
-                        //return Prompt::GrepPattern(typed);
                     }
-                    Key::Char(ch) => {
-                        typed.push(ch);
-                        pager.print(&format!("{}", ch));
+                    _ => {
+                        let entries = history.entries(kind);
+                        edit_line(pager, &mut editor, &mut recall, entries, key, prefix);
                     }
-                    _ => {}
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Completes the word before the cursor one step further. A new completion
+/// starts with `candidates`, which tells where the word begins and what it
+/// may become. The completion is returned while Tab can go on with it.
+fn complete<P, F>(pager: &mut P,
+                  editor: &mut LineEditor,
+                  completion: Option<Completion>,
+                  prefix: &str,
+                  candidates: F)
+                  -> Option<Completion>
+    where P: TermOperations,
+          F: FnOnce(&str) -> (usize, Vec<String>)
+{
+    let mut completion = completion.unwrap_or_else(|| {
+        let (begin, candidates) = candidates(editor.before_cursor());
+        Completion::new(begin, candidates)
+    });
+    let more = completion.next(editor);
+    if more {
+        let column = prefix.width() + editor.text()[..completion.begin()].width();
+        show_candidates(pager, &completion, column);
+    }
+    editor.render(pager, prefix);
+    if more { Some(completion) } else { None }
+}
+
+/// Lists candidates highlighted over the lines right above the status bar,
+/// starting at `column`. The selected one is left plain to stand out.
+fn show_candidates<P>(pager: &mut P, completion: &Completion, column: usize)
+    where P: TermOperations
+{
+    let (width, height) = pager.term_size();
+    let candidates = completion.candidates();
+    let shown = candidates.len().min(POPUP_HEIGHT).min(height.saturating_sub(3));
+    let selected = completion.selected();
+    let first = selected.map_or(0, |idx| (idx + 1).saturating_sub(shown));
+    let cells = candidates.iter().map(|candidate| candidate.width()).max().unwrap_or(0) + 2;
+    let cells = cells.min(width.saturating_sub(1));
+    let column = column.min(width.saturating_sub(cells + 1));
+    let top = height.saturating_sub(3 + shown);
+    for (row, idx) in (first..first + shown).enumerate() {
+        let text = fit_to_cells(&format!(" {}", candidates[idx]), 0, width.max(1), cells);
+        let padded = format!("{}{}", text, " ".repeat(cells.saturating_sub(text.width())));
+        pager.mv_cursor((column, top + row));
+        if selected == Some(idx) {
+            pager.print(&padded);
+        } else {
+            pager.print_highlighted(&padded);
+        }
+    }
+    pager.mv_cursor((0, height.saturating_sub(1)));
+}
+
+/// Applies an editing key to `editor`, Up and Down recall older and newer
+/// `entries` and Ctrl-R searches them. Returns whether the text changed.
+fn edit_line<P>(pager: &mut P,
+                editor: &mut LineEditor,
+                recall: &mut Recall,
+                entries: &[String],
+                key: Key,
+                prefix: &str)
+                -> bool
+    where P: TermOperations
+{
+    let recalled = match key {
+        Key::Up => recall.older(entries, editor.text()),
+        Key::Down => recall.newer(entries),
+        Key::Ctrl('r') => reverse_search(pager, entries),
+        _ => {
+            let edit = editor.handle(key);
+            if edit != Edit::Ignored {
+                editor.render(pager, prefix);
+            }
+            return edit == Edit::Changed;
+        }
+    };
+    let changed = recalled.is_some();
+    if let Some(text) = recalled {
+        *editor = LineEditor::new(&text);
+    }
+    editor.render(pager, prefix);
+    changed
+}
+
+/// Looks for the newest of `entries` containing what is typed, Ctrl-R again
+/// looks further back. Enter picks the entry found, Esc cancels.
+fn reverse_search<P>(pager: &mut P, entries: &[String]) -> Option<String>
+    where P: TermOperations
+{
+    let mut query = LineEditor::new("");
+    let mut found = find_back(entries, "", entries.len());
+    loop {
+        let label = match found {
+            Some(_) => "(reverse-i-search)`",
+            None => "(failing reverse-i-search)`",
+        };
+        let entry = found.map_or("", |idx| entries[idx].as_str());
+        let (width, _) = pager.term_size();
+        pager.clear_line();
+        pager.print(&fit_to_cells(&format!("{}{}': {}", label, query.text(), entry),
+                                  0,
+                                  width.max(1),
+                                  width.saturating_sub(1)));
+        let key = pager.input_key();
+        match key {
+            Key::Enter => return found.map(|idx| entries[idx].clone()),
+            Key::Esc | Key::Ctrl('g') => return None,
+            Key::Ctrl('r') => {
+                let before = found.unwrap_or(entries.len());
+                found = find_back(entries, query.text(), before).or(found);
+            }
+            _ => {
+                if query.handle(key) == Edit::Changed {
+                    found = find_back(entries, query.text(), entries.len());
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,33 @@
 extern crate regex;
 
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::fs::File;
-use std::env;
-use std::path::Path;
-
 use std::cmp::Ordering;
 
-#[allow(unused)]
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Attribute {
     None,
     Inverse,
     Red,
-    Blue
+    Blue,
+    Green,
+    Yellow,
+    Magenta,
+    Cyan,
+}
+
+impl Attribute {
+    pub fn from_name(name: &str) -> Option<Attribute> {
+        match name {
+            "none" => Some(Attribute::None),
+            "inverse" => Some(Attribute::Inverse),
+            "red" => Some(Attribute::Red),
+            "blue" => Some(Attribute::Blue),
+            "green" => Some(Attribute::Green),
+            "yellow" => Some(Attribute::Yellow),
+            "magenta" => Some(Attribute::Magenta),
+            "cyan" => Some(Attribute::Cyan),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -28,6 +41,13 @@ impl DecorationPattern {
         Decorations::Some(self.attributes.clone(), decoration_str)
     }
 
+    pub fn new(pattern: &str, attributes: Vec<Attribute>) -> DecorationPattern {
+        DecorationPattern {
+            pattern: pattern.to_string(),
+            attributes,
+        }
+    }
+
     pub fn from_single_attr(attr: Attribute, pattern: &str) -> DecorationPattern {
         DecorationPattern {
             pattern: pattern.to_string(),
@@ -67,12 +87,21 @@ pub enum Decorations<'a> {
 #[derive(Clone)]
 pub struct Line<'a> {
     pub buffer: &'a str,
+    /// Shown by a grep as context of a match, not as a match itself.
+    pub context: bool,
+    /// Lines right before this one were left out by its grep.
+    pub after_gap: bool,
 }
 
 impl<'a> Line<'a> {
-    fn from(buffer: &'a str) -> Line<'a> {
-        Line { buffer: buffer }
+    pub fn from(buffer: &'a str) -> Line<'a> {
+        Line {
+            buffer,
+            context: false,
+            after_gap: false,
+        }
     }
+
     /// [Decorations::None(&"some"), Decorations::Inverse(&"test b"), Decorations::Inverse(&"cd n"), Decorations::Inverse(&"etc")]
     /// buffer : some test buffer cd nothing etc empty
     /// output : [Decorations::None(&"some "), Decorations::Inverse(&"test b"), Decorations::None(&"uffer")]
@@ -82,7 +111,6 @@ impl<'a> Line<'a> {
     /// uffer cd n |match against all patterns| -> first matched -> None(uffer) && Imp(cd n)
     /// othing etc |match against all pattern| -> first matched -> None(othing ) && Imp(test etc)
     /// empty |match against all pattern| -> none matched -> None(empty)
-
     pub fn decorate(&self,
                     mut decorations_patterns: Vec<DecorationPattern>)
                     -> Vec<Decorations<'a>> {
@@ -162,11 +190,11 @@ impl<'a> Text<'a> {
         Text { lines: Vec::new() }
     }
 
+    #[allow(unused)]
     pub fn from(buffer: &'a str) -> Self {
         Text { lines: buffer.lines().map(Line::from).collect() }
     }
 
-    #[allow(unused)]
     pub fn add_line(&mut self, line: &'a str) {
         self.lines.push(Line::from(line));
     }
@@ -261,6 +289,36 @@ fn match_same_pattern() {
     assert_eq!(decorated_line.len(), 5);
 }
 
+/// Directory for the files of a test, removed with them when the test ends,
+/// whether it passed or not.
+#[cfg(test)]
+pub struct TempDir {
+    pub path: ::std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TempDir {
+    /// An empty directory, `name` tells apart the tests using one.
+    pub fn new(name: &str) -> TempDir {
+        let path = ::std::env::temp_dir()
+            .join(format!("rustgrepper-{}-{}", name, ::std::process::id()));
+        let _ = ::std::fs::remove_dir_all(&path);
+        ::std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn join(&self, name: &str) -> ::std::path::PathBuf {
+        self.path.join(name)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = ::std::fs::remove_dir_all(&self.path);
+    }
+}
+
 pub fn find_closest_index(indexes: &[usize], search: usize) -> Option<usize> {
     let idx = indexes.binary_search(&search);
     if indexes.is_empty() {
@@ -287,7 +345,7 @@ pub fn find_closest_index(indexes: &[usize], search: usize) -> Option<usize> {
                 for (i, number) in indexes.iter().rev().enumerate() {
                     if *number < search {
                         if i != 0 {
-                            return Some(indexes[i - 1]);
+                            return Some(indexes[indexes.len() - i]);
                         } else {
                             return Some(*number);
                         }
@@ -328,27 +386,17 @@ fn find_closest_index_more_data_test() {
 
 
 #[test]
-fn find_closest_index_empty_test() {
-    let test_data = Vec::new();
+fn find_closest_index_gap_test() {
+    let test_data = vec![0, 2, 4, 6];
 
-    assert_eq!(None, find_closest_index(&test_data, 10));
+    assert_eq!(Some(6), find_closest_index(&test_data, 5));
+    assert_eq!(Some(4), find_closest_index(&test_data, 3));
+    assert_eq!(Some(0), find_closest_index(&test_data, 1));
 }
 
-pub fn test_buffer_from_file() -> String {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage:\n\t{} <rust file>", args[0]);
-        println!("Example:\n\t{} examples/ex_5.rs", args[0]);
-        panic!("Unable to open file");
-    }
-    let mut buffer = String::new();
-
-    let f = File::open(Path::new(&args[1])).unwrap();
-    let reader = BufReader::new(f);
+#[test]
+fn find_closest_index_empty_test() {
+    let test_data = Vec::new();
 
-    for line in reader.lines() {
-        buffer.push_str(&line.unwrap());
-        buffer.push_str(&"\n");
-    }
-    buffer
+    assert_eq!(None, find_closest_index(&test_data, 10));
 }
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Lines around the one on top that words are collected from.
+const VOCABULARY_LINES: usize = 100_000;
+/// Candidates offered for a word, the rarer ones are left out.
+const MAX_CANDIDATES: usize = 20;
+
+/// Words seen in lines, like logger names, hostnames and error codes, the
+/// most frequent first.
+pub struct Vocabulary {
+    words: Vec<String>,
+}
+
+impl Vocabulary {
+    pub fn from_lines<'a, I>(lines: I) -> Vocabulary
+        where I: IntoIterator<Item = &'a str>
+    {
+        let mut counts = HashMap::new();
+        for line in lines {
+            for word in words(line) {
+                *counts.entry(word).or_insert(0) += 1;
+            }
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        Vocabulary { words: counts.into_iter().map(|(word, _)| word.to_string()).collect() }
+    }
+
+    /// Words longer than `prefix` that start with it.
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        self.words
+            .iter()
+            .filter(|word| word.len() > prefix.len() && word.starts_with(prefix))
+            .take(MAX_CANDIDATES)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Lines, out of `lines_count`, around `index` to collect words from, a huge
+/// file isn't read whole.
+pub fn around(lines_count: usize, index: usize) -> Range<usize> {
+    let begin = index.saturating_sub(VOCABULARY_LINES / 2).min(lines_count);
+    begin..(begin + VOCABULARY_LINES).min(lines_count)
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '.' || ch == '-'
+}
+
+/// Where the word that ends `text` begins.
+pub fn word_start(text: &str) -> usize {
+    text.rfind(|ch| !is_word_char(ch))
+        .map_or(0, |idx| idx + text[idx..].chars().next().map_or(1, |ch| ch.len_utf8()))
+}
+
+/// Words worth completing in `text`: at least 3 characters with a letter,
+/// dots and dashes only inside them.
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|ch| !is_word_char(ch))
+        .map(|word| word.trim_matches(|ch| ch == '.' || ch == '-'))
+        .filter(|word| word.chars().count() >= 3 && word.chars().any(char::is_alphabetic))
+}
+
+#[test]
+fn collect_words() {
+    assert_eq!(words("12:30:01 [db.pool-2] host-1.example.com: E1234 timeout... 2024-01-02 x1")
+                   .collect::<Vec<_>>(),
+               vec!["db.pool-2", "host-1.example.com", "E1234", "timeout"]);
+    assert_eq!(word_start("-i time"), 3);
+    assert_eq!(word_start("(é or ERR_"), 7);
+    assert_eq!(word_start("timeout"), 0);
+    assert_eq!(word_start("x "), 2);
+}
+
+#[test]
+fn complete_words() {
+    let vocabulary = Vocabulary::from_lines(vec!["ERR timeout on db.pool",
+                                                 "ERR refused on db.primary",
+                                                 "INF db.primary up, ERR_CODE=E42"]);
+    assert_eq!(vocabulary.complete("db"), vec!["db.primary", "db.pool"]);
+    assert_eq!(vocabulary.complete("ER"), vec!["ERR", "ERR_CODE"]);
+    assert_eq!(vocabulary.complete("ERR"), vec!["ERR_CODE"]);
+    assert_eq!(vocabulary.complete("")[..2], ["ERR".to_string(), "db.primary".to_string()]);
+    assert!(vocabulary.complete("x").is_empty());
+    assert_eq!(around(3, 5), 0..3);
+    assert_eq!(around(200_000, 150_000), 100_000..200_000);
+}